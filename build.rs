@@ -1,3 +1,46 @@
+use std::{fs, path::Path};
+
 fn main() {
-    println!(r"cargo:rustc-link-search=./runtime_libs/arm64-v8a")
+    println!(r"cargo:rustc-link-search=./runtime_libs/arm64-v8a");
+
+    // thread_rng gives every peer different numbers, which desyncs the rollback simulation.
+    // Debug builds warn about it in any file that adds rollback systems, RollbackRng is the one to use there.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src");
+    if std::env::var("PROFILE").as_deref() == Ok("debug") {
+        warn_about_thread_rng(Path::new("src"));
+    }
+}
+
+fn warn_about_thread_rng(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            warn_about_thread_rng(&path);
+            continue;
+        }
+        if path.extension().and_then(|ext| ext.to_str()) != Some("rs") {
+            continue;
+        }
+        let Ok(source) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if !source.contains("GgrsSchedule") {
+            continue;
+        }
+        let uses = source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim_start().starts_with("//"))
+            .filter(|(_, line)| line.contains("thread_rng"));
+        for (n, _) in uses {
+            println!(
+                "cargo:warning={}:{}: thread_rng in a file with rollback systems, use RollbackRng instead",
+                path.display(),
+                n + 1
+            );
+        }
+    }
 }
@@ -1,11 +1,15 @@
 use bevy::{math::primitives, prelude::*};
 use bevy_hanabi::prelude::*;
+use serde::{Deserialize, Serialize};
 pub struct AssetHandlesPlugin;
 
+// Spells pick their meshes and materials by these names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MeshName {
     Sphere = 0,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MatName {
     Red = 0,
     Blue,
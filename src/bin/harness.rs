@@ -0,0 +1,6 @@
+#![allow(non_snake_case)]
+fn main() {
+    // There's no running several instances of the game on a headset
+    #[cfg(not(target_os = "android"))]
+    wizARds::harness::main()
+}
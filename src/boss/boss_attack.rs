@@ -1,55 +1,92 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
+use bevy_ggrs::PlayerInputs;
 
-use super::Boss;
+use super::{difficulty, targets_from, Boss, BossTarget};
 use crate::{
     assets::AssetHandles,
+    network::{PlayerID, RollbackRng},
     player::Player,
     projectile::{spawn_projectile, ProjectileType},
+    WizGgrsConfig,
 };
 
-#[derive(Resource)]
+// Time between attacks in a two player match, the boss attacks more often with more players.
+pub const ATTACK_INTERVAL_SECS: f32 = 5.0;
+// Each projectile is thrown up to this far to either side of its player, so standing still isn't always a hit
+const ATTACK_SPREAD_DEGREES: f32 = 8.0;
+
+#[derive(Resource, Clone)]
 pub struct AttackTimer(pub Timer);
 
+// Every attack throws one projectile per two players, each at a different player.
+fn projectiles_per_attack(num_players: usize) -> usize {
+    (num_players / 2).max(1)
+}
+
 pub fn boss_attack(
     mut timer: ResMut<AttackTimer>,
     mut commands: Commands,
     assets: Res<AssetHandles>,
     boss_query: Query<&Transform, (With<Boss>, Without<Player>)>,
-    player_query: Query<&Transform, (With<Player>, Without<Boss>)>,
+    player_query: Query<(&Transform, &PlayerID), (With<Player>, Without<Boss>)>,
+    mut target: ResMut<BossTarget>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    mut rng: ResMut<RollbackRng>,
     time: Res<Time>,
 ) {
+    // Setting the duration keeps the time already elapsed, so this only changes anything the first time
+    let interval = ATTACK_INTERVAL_SECS / difficulty(inputs.len()).sqrt();
+    timer.0.set_duration(Duration::from_secs_f32(interval));
     if timer.0.tick(time.delta()).just_finished() {
         let Ok(boss_transform) = boss_query.get_single() else {
             return;
         };
-        let Some(player_transform) = player_query.iter().next() else {
+        let targets = targets_from(player_query.iter(), *target);
+        if targets.is_empty() {
             return;
-        };
+        }
 
-        let player_pos = Vec3::new(
-            player_transform.translation.x,
-            0.0,
-            player_transform.translation.z,
-        );
         let boss_pos = Vec3::new(
             boss_transform.translation.x,
             0.0,
             boss_transform.translation.z,
         );
-        let projectile_direction = (player_pos - boss_pos).normalize();
         let projectile_start = boss_transform.translation;
+        // With fewer players left than projectiles, some get more than one
+        let volley = targets
+            .iter()
+            .cycle()
+            .take(projectiles_per_attack(inputs.len()));
+        let mut last_handle = target.0;
+        for (player_transform, handle) in volley {
+            let player_pos = Vec3::new(
+                player_transform.translation.x,
+                0.0,
+                player_transform.translation.z,
+            );
+            let spread = rng
+                .range_f32(-ATTACK_SPREAD_DEGREES..ATTACK_SPREAD_DEGREES)
+                .to_radians();
+            let projectile_direction =
+                Quat::from_rotation_y(spread) * (player_pos - boss_pos).normalize();
 
-        let transform = Transform {
-            translation: projectile_start + Vec3::new(0., 1., 0.),
-            rotation: Quat::from_rotation_arc(-Vec3::Z, projectile_direction),
-            ..default()
-        };
+            let transform = Transform {
+                translation: projectile_start + Vec3::new(0., 1., 0.),
+                rotation: Quat::from_rotation_arc(-Vec3::Z, projectile_direction),
+                ..default()
+            };
 
-        spawn_projectile(
-            &mut commands,
-            ProjectileType::BossAttack,
-            &transform,
-            &assets,
-        )
+            spawn_projectile(
+                &mut commands,
+                ProjectileType::BossAttack,
+                &transform,
+                &assets,
+            );
+            last_handle = *handle;
+        }
+        // Go after whoever's next next time
+        target.0 = last_handle + 1;
     }
 }
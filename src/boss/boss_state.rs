@@ -1,17 +1,16 @@
 use bevy::{
     ecs::{
         query::{With, Without},
-        schedule::{NextState, States},
-        system::{Query, Res, ResMut},
+        system::{Query, Res, ResMut, Resource},
     },
     time::Time,
     transform::components::Transform,
 };
 
-use super::{Boss, BossPhase, CurrentPhase};
-use crate::player::Player;
+use super::{target_player, Boss, BossPhase, BossTarget, CurrentPhase};
+use crate::{network::PlayerID, player::Player};
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, States, Default)]
+#[derive(Resource, Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
 pub enum BossState {
     #[default]
     Idle,
@@ -21,11 +20,12 @@ pub enum BossState {
 
 pub fn boss_action(
     mut query: Query<&mut Transform, (With<Boss>, Without<Player>)>,
-    player_query: Query<&Transform, (With<Player>, Without<Boss>)>,
-    mut state: ResMut<NextState<BossState>>,
+    player_query: Query<(&Transform, &PlayerID), (With<Player>, Without<Boss>)>,
+    mut state: ResMut<BossState>,
     phase: Res<CurrentPhase>,
+    target: Res<BossTarget>,
 ) {
-    let Some(player_transform) = player_query.iter().next() else {
+    let Some(player_transform) = target_player(player_query.iter(), *target) else {
         return;
     };
     let Ok(boss_transform) = query.get_single_mut() else {
@@ -39,28 +39,29 @@ pub fn boss_action(
     // change boss state depend on distance
     // TODO this is bad, i should collpase boss state and boss phase behaviour together, but time.
     if distance > 10.0 {
-        state.set(BossState::Idle);
+        *state = BossState::Idle;
     } else if distance > 7.0 {
         if phase.0 == BossPhase::Phase1 {
-            state.set(BossState::Idle);
+            *state = BossState::Idle;
         } else {
-            state.set(BossState::MoveTowardsPlayer);
+            *state = BossState::MoveTowardsPlayer;
         }
     } else if distance >= 0.0 {
         if phase.0 == BossPhase::Phase1 {
-            state.set(BossState::Idle);
+            *state = BossState::Idle;
         } else {
-            state.set(BossState::Attack);
+            *state = BossState::Attack;
         }
     }
 }
 
 pub fn boss_move(
     mut query: Query<&mut Transform, (With<Boss>, Without<Player>)>,
-    player_query: Query<&Transform, (With<Player>, Without<Boss>)>,
+    player_query: Query<(&Transform, &PlayerID), (With<Player>, Without<Boss>)>,
+    target: Res<BossTarget>,
     time: Res<Time>,
 ) {
-    let Some(player_transform) = player_query.iter().next() else {
+    let Some(player_transform) = target_player(player_query.iter(), *target) else {
         return;
     };
     let Ok(mut boss_transform) = query.get_single_mut() else {
@@ -1,18 +1,36 @@
 mod boss_attack;
 mod boss_state;
 
-use std::f32::consts::PI;
+use std::{f32::consts::PI, hash::Hasher};
 
 use bevy::prelude::*;
+use bevy_ggrs::{AddRollbackCommandExtension, GgrsApp, GgrsSchedule, PlayerInputs};
 use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use self::{
-    boss_attack::{boss_attack, AttackTimer},
+    boss_attack::{boss_attack, AttackTimer, ATTACK_INTERVAL_SECS},
     boss_state::{boss_action, boss_move, BossState},
 };
-use crate::{player::Player, projectile::DamageMask, PhysLayer};
+use crate::{
+    network::{
+        hash_floats, hash_timer, stable_hash, MatchState, PlayerID, RollbackChecksumApp,
+        SessionRestarted, StableHasher,
+    },
+    player::Player,
+    projectile::{handle_reset_phase_hits, DamageMask},
+    PhysLayer, WizGgrsConfig,
+};
+
+// The boss is tuned for this many players, and scaled up or down from there.
+const BASE_PLAYERS: usize = 2;
+
+// How much tougher the boss is than in a two player match, 1.5 with three players and so on.
+pub fn difficulty(num_players: usize) -> f32 {
+    num_players.max(1) as f32 / BASE_PLAYERS as f32
+}
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 pub struct BossHealth {
     pub max: f32,
     pub current: f32,
@@ -20,7 +38,7 @@ pub struct BossHealth {
 }
 
 // This implementation of phases is gross.
-#[derive(Debug, Clone, Eq, PartialEq, Hash, States, Default, Copy)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default, Copy, Serialize, Deserialize)]
 pub enum BossPhase {
     #[default]
     Phase1,
@@ -31,14 +49,15 @@ pub enum BossPhase {
 }
 
 impl BossPhase {
-    pub fn max_health(self) -> f32 {
-        match self {
+    pub fn max_health(self, num_players: usize) -> f32 {
+        let base = match self {
             Self::Phase1 => 50.,
             Self::Phase2 => 50.,
             Self::Phase3 => 50.,
             Self::Dead => 0.,
             Self::Reset => 0.,
-        }
+        };
+        base * difficulty(num_players)
     }
 
     pub fn next_phase(self) -> Self {
@@ -52,73 +71,184 @@ impl BossPhase {
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone, Copy, Hash)]
 pub struct CurrentPhase(pub BossPhase);
 
+// Phase the boss moves to at the end of the frame, if anything asked it to change.
+#[derive(Resource, Default, Clone, Copy, Hash)]
+pub struct NextPhase(pub Option<BossPhase>);
+
+// Handle of the player the boss is going after. It moves on to the next player after every attack,
+// so with more players the boss spreads its attention around.
+#[derive(Resource, Default, Clone, Copy, Hash, Debug)]
+pub struct BossTarget(pub usize);
+
 impl BossHealth {
     pub fn normalized_value(&self) -> f32 {
         self.current / self.max
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub struct Boss;
 
 pub struct BossPlugin;
 
 impl Plugin for BossPlugin {
     fn build(&self, app: &mut App) {
-        app.init_state::<BossState>()
-            .init_state::<BossPhase>()
+        app.init_resource::<BossState>()
             .insert_resource(CurrentPhase(BossPhase::Phase1))
+            .init_resource::<NextPhase>()
+            .init_resource::<BossTarget>()
+            .insert_resource(AttackTimer(Timer::from_seconds(
+                ATTACK_INTERVAL_SECS,
+                TimerMode::Repeating,
+            )))
+            .rollback_component_with_copy::<Boss>()
+            .rollback_component_with_clone::<BossHealth>()
+            .checksum_rollback_component::<BossHealth>(|h| {
+                let mut hasher = StableHasher::default();
+                hasher.write_u64(hash_floats(&[h.max, h.current]));
+                hasher.write_u8(h.damage_mask.0);
+                hasher.finish()
+            })
+            .rollback_resource_with_copy::<BossState>()
+            .checksum_resource::<BossState>(stable_hash)
+            .rollback_resource_with_copy::<CurrentPhase>()
+            .checksum_resource::<CurrentPhase>(stable_hash)
+            .rollback_resource_with_copy::<NextPhase>()
+            .checksum_resource::<NextPhase>(stable_hash)
+            .rollback_resource_with_copy::<BossTarget>()
+            .checksum_resource::<BossTarget>(stable_hash)
+            .rollback_resource_with_clone::<AttackTimer>()
+            .checksum_resource::<AttackTimer>(|t| hash_timer(&t.0))
             .add_systems(Startup, setup)
-            .insert_resource(AttackTimer(Timer::from_seconds(5.0, TimerMode::Repeating)))
+            // After the network plugin has sent the restart, so the boss is back before the new session starts
+            .add_systems(PostUpdate, restart_boss)
+            // The boss is simulated by every peer, so it has to run on the GgrsSchedule like everything else
             .add_systems(
-                Update,
+                GgrsSchedule,
                 (
+                    fit_boss_to_players,
                     update_boss,
                     boss_action,
-                    boss_attack.run_if(in_state(BossState::Attack)),
-                    boss_move.run_if(in_state(BossState::MoveTowardsPlayer)),
+                    boss_attack.run_if(resource_equals(BossState::Attack)),
+                    boss_move.run_if(resource_equals(BossState::MoveTowardsPlayer)),
                     check_phase,
-                ),
-            )
-            .add_systems(OnEnter(BossPhase::Phase2), init_phase2)
-            .add_systems(OnEnter(BossPhase::Phase3), init_phase3)
-            .add_systems(OnEnter(BossPhase::Reset), reset_phase)
-            .add_systems(OnEnter(BossPhase::Dead), despawn_boss);
+                    apply_next_phase,
+                )
+                    .chain()
+                    .after(handle_reset_phase_hits)
+                    .run_if(resource_equals(MatchState::Running)),
+            );
     }
 }
 
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_boss(&mut commands, &asset_server, BossPhase::Phase1);
+}
+
+fn spawn_boss(commands: &mut Commands, asset_server: &AssetServer, phase: BossPhase) {
     let model = asset_server.load("white bear.glb#Scene0");
 
-    let initial_mask: DamageMask = DamageMask(DamageMask::FIRE.0 | DamageMask::LIGHTNING.0);
+    let damage_mask = match phase {
+        BossPhase::Phase3 => DamageMask::LIGHTNING,
+        _ => DamageMask(DamageMask::FIRE.0 | DamageMask::LIGHTNING.0),
+    };
 
-    commands.spawn((
-        SceneBundle {
-            scene: model,
-            transform: Transform::from_xyz(0.0, 0.4, 0.0).with_scale(Vec3::new(1.0, 2.5, 1.0)),
-            ..default()
-        },
-        RigidBody::Kinematic,
-        Collider::cuboid(0.25, 0.25, 0.25),
-        CollisionLayers::new(PhysLayer::Boss, LayerMask::ALL ^ PhysLayer::BossProjectile),
-        Boss,
-        BossHealth {
-            max: BossPhase::Phase1.max_health(),
-            current: BossPhase::Phase1.max_health(),
-            damage_mask: initial_mask,
-        },
-    ));
+    commands
+        .spawn((
+            SceneBundle {
+                scene: model,
+                transform: Transform::from_xyz(0.0, 0.4, 0.0).with_scale(Vec3::new(1.0, 2.5, 1.0)),
+                ..default()
+            },
+            RigidBody::Kinematic,
+            Collider::cuboid(0.25, 0.25, 0.25),
+            CollisionLayers::new(PhysLayer::Boss, LayerMask::ALL ^ PhysLayer::BossProjectile),
+            Boss,
+            // Brought up to strength for however many players there are on the first frame of the session
+            BossHealth {
+                max: phase.max_health(BASE_PLAYERS),
+                current: phase.max_health(BASE_PLAYERS),
+                damage_mask,
+            },
+        ))
+        .add_rollback();
+}
+
+// The old boss went with every other rollback entity, start the phase it was in over again.
+fn restart_boss(
+    mut commands: Commands,
+    mut restarts: EventReader<SessionRestarted>,
+    asset_server: Res<AssetServer>,
+    mut current_phase: ResMut<CurrentPhase>,
+    mut next_phase: ResMut<NextPhase>,
+    mut target: ResMut<BossTarget>,
+    mut state: ResMut<BossState>,
+    mut attack_timer: ResMut<AttackTimer>,
+) {
+    let Some(restart) = restarts.read().last() else {
+        return;
+    };
+    current_phase.0 = restart.boss_phase;
+    next_phase.0 = None;
+    *target = BossTarget::default();
+    *state = BossState::default();
+    attack_timer.0.reset();
+    if restart.boss_phase != BossPhase::Dead {
+        spawn_boss(&mut commands, &asset_server, restart.boss_phase);
+    }
+}
+
+// Every player, in the order the boss goes after them, starting with its target.
+// If the target has gone the next player by handle takes their place.
+// Ordered by handle rather than query order, so every peer agrees.
+fn targets_from<'a>(
+    players: impl Iterator<Item = (&'a Transform, &'a PlayerID)>,
+    target: BossTarget,
+) -> Vec<(&'a Transform, usize)> {
+    let mut players: Vec<_> = players.map(|(t, id)| (t, id.handle)).collect();
+    players.sort_by_key(|(_, handle)| *handle);
+    let first = players
+        .iter()
+        .position(|(_, handle)| *handle >= target.0)
+        .unwrap_or(0);
+    players.rotate_left(first);
+    players
+}
+
+// The player the boss is going after.
+fn target_player<'a>(
+    players: impl Iterator<Item = (&'a Transform, &'a PlayerID)>,
+    target: BossTarget,
+) -> Option<&'a Transform> {
+    targets_from(players, target).first().map(|(t, _)| *t)
+}
+
+// The boss is spawned before anyone knows how many players there'll be, so it's scaled to them on its first frame.
+fn fit_boss_to_players(
+    phase: Res<CurrentPhase>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    mut boss_health: Query<&mut BossHealth, With<Boss>>,
+) {
+    let Ok(mut health) = boss_health.get_single_mut() else {
+        return;
+    };
+    let max = phase.0.max_health(inputs.len());
+    if health.max > 0.0 && health.max != max {
+        health.current *= max / health.max;
+        health.max = max;
+    }
 }
 
 // boss look at player
 fn update_boss(
     mut query: Query<&mut Transform, (With<Boss>, Without<Player>)>,
-    player_query: Query<&Transform, (With<Player>, Without<Boss>)>,
+    player_query: Query<(&Transform, &PlayerID), (With<Player>, Without<Boss>)>,
+    target: Res<BossTarget>,
 ) {
-    if let Some(player_transform) = player_query.iter().next() {
+    if let Some(player_transform) = target_player(player_query.iter(), *target) {
         let Ok(mut boss_transform) = query.get_single_mut() else {
             return;
         };
@@ -136,62 +266,69 @@ fn update_boss(
     }
 }
 
-fn init_phase2(mut boss_health: Query<&mut BossHealth>, mut current_phase: ResMut<CurrentPhase>) {
-    println!("Enter Phase 2.");
-    let Ok(mut health) = boss_health.get_single_mut() else {
-        return;
-    };
-
-    health.current = current_phase.0.max_health();
-    health.max = current_phase.0.max_health();
-
-    // TODO This is dumb, but I don't have time to think of a better way to do this, do better in future.
-    current_phase.0 = BossPhase::Phase2;
-}
-
-fn init_phase3(mut boss_health: Query<&mut BossHealth>, mut current_phase: ResMut<CurrentPhase>) {
-    println!("Enter Phase 3.");
-    let Ok(mut health) = boss_health.get_single_mut() else {
-        return;
-    };
-
-    current_phase.0 = BossPhase::Phase3;
-
-    health.damage_mask = DamageMask::LIGHTNING;
-    health.current = current_phase.0.max_health();
-    health.max = current_phase.0.max_health();
-
-    // TODO This is dumb, but I don't have time to think of a better way to do this, do better in future.
-}
-
-// TODO A phase that just goes back to the start of the current phase seems dumb, do it in a better way.
-fn reset_phase(current_phase: Res<CurrentPhase>, mut next_phase: ResMut<NextState<BossPhase>>) {
-    println!("Resetting Phase.");
-    next_phase.set(current_phase.0);
-}
-
 fn check_phase(
     current_phase: Res<CurrentPhase>,
-    mut next_phase: ResMut<NextState<BossPhase>>,
+    mut next_phase: ResMut<NextPhase>,
     boss_health: Query<&BossHealth>,
 ) {
     let Ok(health) = boss_health.get_single() else {
         return;
     };
     if health.current <= 0.0 {
-        next_phase.set(current_phase.0.next_phase());
+        next_phase.0 = Some(current_phase.0.next_phase());
     }
 }
 
-fn despawn_boss(mut commands: Commands, mut boss: Query<(Entity, &Transform), With<Boss>>) {
-    let Ok((boss_e, t)) = boss.get_single_mut() else {
+fn apply_next_phase(
+    mut commands: Commands,
+    mut next_phase: ResMut<NextPhase>,
+    mut current_phase: ResMut<CurrentPhase>,
+    mut boss: Query<(Entity, &Transform, &mut BossHealth), With<Boss>>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+) {
+    let Some(phase) = next_phase.0.take() else {
         return;
     };
+    let Ok((boss_e, t, mut health)) = boss.get_single_mut() else {
+        return;
+    };
+
+    // TODO A phase that just goes back to the start of the current phase seems dumb, do it in a better way.
+    let phase = match phase {
+        BossPhase::Reset => {
+            info!("boss resetting {:?}", current_phase.0);
+            current_phase.0
+        }
+        phase => phase,
+    };
+    current_phase.0 = phase;
 
-    commands.spawn(TransformBundle {
-        local: Transform::clone(t).with_rotation(Quat::from_axis_angle(Vec3::Y, (2.0 * PI) / 2.0)),
-        ..default()
-    });
+    match phase {
+        BossPhase::Phase1 | BossPhase::Phase2 => {
+            info!("boss entering {phase:?}");
+            health.current = phase.max_health(inputs.len());
+            health.max = phase.max_health(inputs.len());
+        }
+        BossPhase::Phase3 => {
+            info!("boss entering {phase:?}");
+            health.damage_mask = DamageMask::LIGHTNING;
+            health.current = phase.max_health(inputs.len());
+            health.max = phase.max_health(inputs.len());
+        }
+        BossPhase::Dead => despawn_boss(&mut commands, boss_e, t),
+        // The current phase is never Reset
+        BossPhase::Reset => {}
+    }
+}
+
+fn despawn_boss(commands: &mut Commands, boss_e: Entity, t: &Transform) {
+    commands
+        .spawn(TransformBundle {
+            local: Transform::clone(t)
+                .with_rotation(Quat::from_axis_angle(Vec3::Y, (2.0 * PI) / 2.0)),
+            ..default()
+        })
+        .add_rollback();
 
     commands.entity(boss_e).despawn_recursive();
 }
@@ -0,0 +1,318 @@
+use bevy::prelude::*;
+use bevy_ggrs::{GgrsApp, GgrsSchedule};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mana::Mana,
+    network::{
+        hash_floats, move_networked_player_objs, spawn_head_panel, stable_hash, MatchState,
+        PlayerHead, PlayerID, RollbackChecksumApp, SessionRestarted, MAX_PLAYERS,
+    },
+    projectile::{update_linear_movement, Caster, DamageHit, Projectile, ProjectileHitEffect},
+    FPS,
+};
+
+const PLAYER_MAX_HEALTH: f32 = 100.0;
+// Radius of the players' head colliders and of the player projectiles, at a scale of 1.
+const HEAD_RADIUS: f32 = 0.1;
+const PROJECTILE_RADIUS: f32 = 0.1;
+// Winning this many rounds wins the match, and the next round starts a new one.
+pub const ROUNDS_TO_WIN: u32 = 3;
+// How long everyone gets to see who won the round before the next one starts.
+const ROUND_OVER_FRAMES: u32 = 3 * FPS as u32;
+const PANEL_SIZE: Vec2 = Vec2::new(0.3, 0.1);
+// Up and to the left, out of the way of the fight.
+const PANEL_OFFSET: Vec3 = Vec3::new(-0.3, 0.2, -0.6);
+
+// Only players' heads have health, hands are fair game to block with.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlayerHealth {
+    pub max: f32,
+    pub current: f32,
+}
+
+impl PlayerHealth {
+    fn full() -> Self {
+        Self {
+            max: PLAYER_MAX_HEALTH,
+            current: PLAYER_MAX_HEALTH,
+        }
+    }
+
+    pub fn normalized_value(&self) -> f32 {
+        self.current / self.max
+    }
+}
+
+// Rounds won by each player, indexed by handle, in the current match.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DuelScore {
+    // Counts up from 0 for the first round of a match
+    pub round: u32,
+    pub wins: [u32; MAX_PLAYERS],
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RoundState {
+    #[default]
+    Fighting,
+    // Nobody wins if the last players standing go down on the same frame
+    Over {
+        winner: Option<usize>,
+        frames_left: u32,
+    },
+}
+
+#[derive(Component, Clone)]
+struct ScorePanel;
+
+#[derive(Component)]
+struct ScorePanelText;
+
+pub struct DuelPlugin;
+
+impl Plugin for DuelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DuelScore>()
+            .init_resource::<RoundState>()
+            .rollback_component_with_copy::<PlayerHealth>()
+            .checksum_rollback_component::<PlayerHealth>(|h| hash_floats(&[h.max, h.current]))
+            .rollback_resource_with_copy::<DuelScore>()
+            .checksum_resource::<DuelScore>(stable_hash)
+            .rollback_resource_with_copy::<RoundState>()
+            .checksum_resource::<RoundState>(stable_hash)
+            .add_systems(Startup, spawn_score_panel)
+            .add_systems(
+                Update,
+                show_score
+                    .run_if(resource_changed::<DuelScore>.or_else(resource_changed::<RoundState>)),
+            )
+            // After the network plugin has sent the restart, so the score is back before the new session starts
+            .add_systems(PostUpdate, restart_duel)
+            // Every peer judges every hit, so like the boss this has to run on the GgrsSchedule
+            .add_systems(
+                GgrsSchedule,
+                (
+                    give_players_health,
+                    handle_head_hits,
+                    check_round_over,
+                    next_round,
+                )
+                    .chain()
+                    .after(move_networked_player_objs)
+                    .after(update_linear_movement)
+                    .run_if(resource_equals(MatchState::Running)),
+            );
+    }
+}
+
+// Players join with full health, including ones that come back after a restart.
+fn give_players_health(
+    mut commands: Commands,
+    heads: Query<Entity, (With<PlayerHead>, Without<PlayerHealth>)>,
+) {
+    for e in heads.iter() {
+        commands.entity(e).insert(PlayerHealth::full());
+    }
+}
+
+// Physics runs outside the rollback schedule, so its collision events can't be trusted to land on the
+// same frame on every peer. Instead every projectile is checked against every other player's head here.
+fn handle_head_hits(
+    mut commands: Commands,
+    round: Res<RoundState>,
+    projectiles: Query<(Entity, &Transform, &Caster, &ProjectileHitEffect), With<Projectile>>,
+    mut heads: Query<(&Transform, &PlayerID, &mut PlayerHealth), With<PlayerHead>>,
+) {
+    if *round != RoundState::Fighting {
+        return;
+    }
+    let mut hits = Vec::new();
+    for (e, t, caster, effect) in projectiles.iter() {
+        let ProjectileHitEffect::Damage(DamageHit(_, damage)) = effect else {
+            continue;
+        };
+        let reach = HEAD_RADIUS + PROJECTILE_RADIUS * t.scale.max_element();
+        // A projectile touching two heads at once hits whoever has the lowest handle, so every peer agrees
+        let hit = heads
+            .iter()
+            .filter(|(head, id, _)| {
+                id.handle != caster.0 && head.translation.distance(t.translation) < reach
+            })
+            .map(|(_, id, _)| id.handle)
+            .min();
+        if let Some(handle) = hit {
+            hits.push((handle, *damage));
+            commands.entity(e).despawn_recursive();
+        }
+    }
+
+    damage_heads(hits, &mut heads);
+}
+
+// Only duels have rounds, and players only get hurt while one's being fought.
+pub(crate) fn players_can_be_hurt(round: Option<Res<RoundState>>) -> bool {
+    round.is_some_and(|r| *r == RoundState::Fighting)
+}
+
+// Takes each hit's damage away from the head of the player with that handle.
+pub(crate) fn damage_heads(
+    mut hits: Vec<(usize, f32)>,
+    heads: &mut Query<(&Transform, &PlayerID, &mut PlayerHealth), With<PlayerHead>>,
+) {
+    // Query order can differ between peers, and so would the rounding of the health if it was
+    // taken away in that order, so apply the hits in an order every peer agrees on
+    hits.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    for (handle, damage) in hits {
+        if let Some((_, _, mut health)) = heads.iter_mut().find(|(_, id, _)| id.handle == handle) {
+            health.current = (health.current - damage).max(0.0);
+        }
+    }
+}
+
+// How far along a ray it passes through a head, if it does before `range`.
+// Missiles are raycast against colliders, which like projectiles can't be trusted to hit heads.
+pub(crate) fn ray_hits_head(origin: Vec3, dir: Vec3, range: f32, head: Vec3) -> Option<f32> {
+    let along = (head - origin).dot(dir);
+    let passes_through = (origin + along * dir).distance(head) < HEAD_RADIUS;
+    ((0.0..range).contains(&along) && passes_through).then_some(along)
+}
+
+// Whether a blast centred here reaches a head.
+pub(crate) fn blast_hits_head(centre: Vec3, radius: f32, head: Vec3) -> bool {
+    centre.distance(head) < radius + HEAD_RADIUS
+}
+
+fn check_round_over(
+    heads: Query<(&PlayerID, &PlayerHealth), With<PlayerHead>>,
+    mut round: ResMut<RoundState>,
+    mut score: ResMut<DuelScore>,
+) {
+    // Someone left on their own has nobody to fight, so they wait for the others to come back
+    if *round != RoundState::Fighting || heads.iter().count() < 2 {
+        return;
+    }
+    let mut standing = heads
+        .iter()
+        .filter(|(_, health)| health.current > 0.0)
+        .map(|(id, _)| id.handle);
+    let winner = standing.next();
+    if standing.next().is_some() {
+        return;
+    }
+
+    match winner {
+        Some(handle) => {
+            score.wins[handle] += 1;
+            info!("player {handle} wins round {}", score.round + 1);
+        }
+        None => info!("round {} is a draw", score.round + 1),
+    }
+    *round = RoundState::Over {
+        winner,
+        frames_left: ROUND_OVER_FRAMES,
+    };
+}
+
+fn next_round(
+    mut commands: Commands,
+    mut round: ResMut<RoundState>,
+    mut score: ResMut<DuelScore>,
+    mut players: Query<(&mut PlayerHealth, Option<&mut Mana>)>,
+    projectiles: Query<Entity, With<Projectile>>,
+) {
+    let RoundState::Over {
+        winner,
+        frames_left,
+    } = *round
+    else {
+        return;
+    };
+    if frames_left > 0 {
+        *round = RoundState::Over {
+            winner,
+            frames_left: frames_left - 1,
+        };
+        return;
+    }
+
+    if score.wins.iter().any(|&w| w >= ROUNDS_TO_WIN) {
+        *score = DuelScore::default();
+    } else {
+        score.round += 1;
+    }
+    for (mut health, mana) in players.iter_mut() {
+        *health = PlayerHealth::full();
+        if let Some(mut mana) = mana {
+            *mana = Mana::full();
+        }
+    }
+    // Nothing from the last round carries over into the next
+    for e in projectiles.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    *round = RoundState::Fighting;
+}
+
+// The players came back with the rest of the rollback entities, so just pick the score back up.
+fn restart_duel(
+    mut restarts: EventReader<SessionRestarted>,
+    mut score: ResMut<DuelScore>,
+    mut round: ResMut<RoundState>,
+) {
+    let Some(restart) = restarts.read().last() else {
+        return;
+    };
+    *score = restart.duel_score;
+    *round = RoundState::Fighting;
+}
+
+fn spawn_score_panel(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let text = spawn_head_panel(
+        &mut commands,
+        &mut images,
+        &mut meshes,
+        &mut materials,
+        PANEL_SIZE,
+        PANEL_OFFSET,
+        "",
+        ScorePanel,
+    );
+    commands.entity(text).insert(ScorePanelText);
+}
+
+fn show_score(
+    score: Res<DuelScore>,
+    round: Res<RoundState>,
+    mut text: Query<&mut Text, With<ScorePanelText>>,
+) {
+    let Ok(mut text) = text.get_single_mut() else {
+        return;
+    };
+    let wins: Vec<String> = score
+        .wins
+        .iter()
+        .enumerate()
+        .filter(|(_, &w)| w > 0)
+        .map(|(handle, w)| format!("P{handle}: {w}"))
+        .collect();
+    let mut s = format!("Round {}", score.round + 1);
+    if !wins.is_empty() {
+        s = format!("{s}  {}", wins.join("  "));
+    }
+    if let RoundState::Over { winner, .. } = *round {
+        let match_won = winner.is_some_and(|w| score.wins[w] >= ROUNDS_TO_WIN);
+        s.push('\n');
+        s.push_str(&match winner {
+            Some(w) if match_won => format!("Player {w} wins the match!"),
+            Some(w) => format!("Player {w} wins the round"),
+            None => "Draw".to_owned(),
+        });
+    }
+    text.sections[0].value = s;
+}
@@ -0,0 +1,608 @@
+// Runs a whole networked session on one machine, without any headsets.
+// A handful of headless instances play each other over loopback, driven by scripted inputs,
+// then every instance writes down the state of the game at the same frame and they're compared.
+//
+//     cargo run --bin harness -- --players 3 --frames 1440
+//
+// With `--session synctest` every player runs in this one process instead, and every spell is checked
+// against its caster's input as it's cast.
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    prelude::*,
+    render::{
+        settings::{RenderCreation, WgpuSettings},
+        RenderPlugin,
+    },
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_ggrs::{prelude::*, PlayerInputs, RollbackFrameCount, SaveWorld};
+
+use crate::{
+    boss::{Boss, BossHealth},
+    duel::{DuelScore, PlayerHealth, RoundState},
+    input::{POSITION_PRECISION, ROTATION_PRECISION},
+    mana::Mana,
+    network::{
+        AimMode, ConnectionSettings, GameMode, InputScript, PlayerHead, PlayerID, PlayerLeftPalm,
+        PlayerRightPalm, SessionMode, SharedAnchor, MAX_PLAYERS,
+    },
+    spell_control::{aim_spell, spawn_new_spell_entities},
+    spell_registry::SpellRegistry,
+    spells::{handle_projectile_spells, SpellObj},
+    PlayerInput, WizGgrsConfig, FPS,
+};
+
+const USAGE: &str = "\
+Usage: harness [options]
+    --players <n>       number of instances to run, 2 to 6 [default: 2]
+    --frames <n>        frame to compare every instance's state at [default: 720]
+    --base-port <port>  GGRS port of the first instance, the rest count up from it [default: 9100]
+    --relay <addr>      send every instance's traffic through the relay server at this ip:port
+    --mode <boss|duel>  what the instances play [default: boss]
+    --session <p2p|synctest>
+                        synctest runs every player in this process, checking each spell leaves its
+                        caster's hands [default: p2p]";
+
+const MIN_PLAYERS: usize = 2;
+// Wire ids of the built-in fireball, magic missile and guided bolt, from assets/spells.ron.
+// None outlives the frame it's cast on, which the synctest's spell check relies on.
+const SCRIPTED_SPELLS: [u32; 3] = [1, 6, 8];
+// Instances keep playing this many frames past the compared one, so the slower ones
+// still have someone to get their inputs from.
+const LINGER_FRAMES: i32 = 2 * FPS as i32;
+// Real time allowed on top of the frames themselves, for connecting and for slow machines.
+const TIMEOUT_SLACK_SECS: f32 = 30.0;
+// How far a spell can be from where its caster's input puts it, in metres, and how far it can be
+// turned from the way the input aims it, in radians. Each is a couple of the input's quantization steps.
+const SPELL_ORIGIN_TOLERANCE: f32 = 2.0 * POSITION_PRECISION;
+const SPELL_AIM_TOLERANCE: f32 = 2.0 * ROTATION_PRECISION;
+
+struct HarnessArgs {
+    players: usize,
+    frames: i32,
+    base_port: u16,
+    relay: Option<String>,
+    mode: GameMode,
+    session: SessionMode,
+    // Set when this process is one of the instances, rather than the one starting them
+    instance: Option<usize>,
+    snapshot: Option<PathBuf>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<HarnessArgs, String> {
+    let mut parsed = HarnessArgs {
+        players: MIN_PLAYERS,
+        frames: 10 * FPS as i32,
+        base_port: 9100,
+        relay: None,
+        mode: GameMode::default(),
+        session: SessionMode::P2P,
+        instance: None,
+        snapshot: None,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Err(USAGE.to_owned());
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{arg} needs a value\n\n{USAGE}"))?;
+        let invalid =
+            |e: &dyn std::fmt::Display| format!("invalid value \"{value}\" for {arg}: {e}");
+        match arg.as_str() {
+            "--players" => parsed.players = value.parse().map_err(|e| invalid(&e))?,
+            "--frames" => parsed.frames = value.parse().map_err(|e| invalid(&e))?,
+            "--base-port" => parsed.base_port = value.parse().map_err(|e| invalid(&e))?,
+            "--relay" => parsed.relay = Some(value),
+            "--mode" => parsed.mode = value.parse().map_err(|e| invalid(&e))?,
+            "--session" => parsed.session = value.parse().map_err(|e| invalid(&e))?,
+            "--instance" => parsed.instance = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--snapshot" => parsed.snapshot = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown argument \"{arg}\"\n\n{USAGE}")),
+        }
+    }
+    if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&parsed.players) {
+        return Err(format!(
+            "--players must be between {MIN_PLAYERS} and {MAX_PLAYERS}"
+        ));
+    }
+    if parsed.frames <= 0 {
+        return Err("--frames must be more than 0".to_owned());
+    }
+    Ok(parsed)
+}
+
+pub fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let passed = match (args.instance, &args.snapshot) {
+        (Some(instance), Some(snapshot)) => run_instance(&args, instance, snapshot),
+        _ if args.session == SessionMode::SyncTest => run_synctest(&args),
+        _ => run_session(&args),
+    };
+    if !passed {
+        std::process::exit(1);
+    }
+}
+
+// Starts an instance per player, waits for them all, then compares what they saw.
+fn run_session(args: &HarnessArgs) -> bool {
+    let dir = std::env::temp_dir().join(format!("wizards-harness-{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("failed to create {}: {e}", dir.display());
+        return false;
+    }
+    let exe = std::env::current_exe().expect("the harness knows where it is");
+    let snapshots: Vec<PathBuf> = (0..args.players)
+        .map(|i| dir.join(format!("instance{i}.txt")))
+        .collect();
+
+    let children: Vec<std::io::Result<Child>> = snapshots
+        .iter()
+        .enumerate()
+        .map(|(i, snapshot)| {
+            let mut command = Command::new(&exe);
+            if let Some(relay) = &args.relay {
+                command.args(["--relay", relay]);
+            }
+            command
+                .args(["--players", &args.players.to_string()])
+                .args(["--frames", &args.frames.to_string()])
+                .args(["--base-port", &args.base_port.to_string()])
+                .args(["--mode", &args.mode.to_string()])
+                .args(["--instance", &i.to_string()])
+                .arg("--snapshot")
+                .arg(snapshot)
+                .spawn()
+        })
+        .collect();
+    let mut passed = true;
+    for (i, child) in children.into_iter().enumerate() {
+        match child.and_then(|mut c| c.wait()) {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                eprintln!("instance {i} failed: {status}");
+                passed = false;
+            }
+            Err(e) => {
+                eprintln!("failed to run instance {i}: {e}");
+                passed = false;
+            }
+        }
+    }
+
+    let snapshots: Vec<Option<String>> = snapshots
+        .iter()
+        .map(|path| std::fs::read_to_string(path).ok())
+        .collect();
+    let _ = std::fs::remove_dir_all(&dir);
+    let Some(Some(expected)) = snapshots.first() else {
+        eprintln!("instance 0 didn't reach frame {}", args.frames);
+        return false;
+    };
+    for (i, snapshot) in snapshots.iter().enumerate().skip(1) {
+        match snapshot {
+            None => {
+                eprintln!("instance {i} didn't reach frame {}", args.frames);
+                passed = false;
+            }
+            Some(snapshot) if snapshot != expected => {
+                eprintln!(
+                    "instance {i} disagrees with instance 0 at frame {}:",
+                    args.frames
+                );
+                for (ours, theirs) in expected.lines().zip(snapshot.lines()) {
+                    if ours != theirs {
+                        eprintln!("  instance 0: {ours}\n  instance {i}: {theirs}");
+                    }
+                }
+                if expected.lines().count() != snapshot.lines().count() {
+                    eprintln!("  instance 0:\n{expected}  instance {i}:\n{snapshot}");
+                }
+                passed = false;
+            }
+            Some(_) => {}
+        }
+    }
+    if passed {
+        println!(
+            "all {} instances agree at frame {}:\n{expected}",
+            args.players, args.frames
+        );
+    }
+    passed
+}
+
+// Each player stands a metre along from the last, swaying about and casting every couple of seconds.
+// Only the sending instance runs this, everyone else gets the inputs over the network.
+fn scripted_input(handle: usize, frame: u32) -> PlayerInput {
+    let t = frame as f32 / FPS as f32;
+    let sway = (t + handle as f32).sin() * 0.1;
+    let head = Vec3::new(handle as f32 + sway, 1.6, 2.0);
+    let facing = Quat::from_rotation_y(sway);
+
+    let mut input = PlayerInput::default();
+    input.set_head(head, facing);
+    input.set_left_hand(head + facing * Vec3::new(-0.15, -0.4, -0.3), facing);
+    input.set_right_hand(head + facing * Vec3::new(0.15, -0.4, -0.3), facing);
+    // Each player aims a different way, so every kind of aiming is checked
+    input.set_aim_mode([AimMode::Head, AimMode::Palms, AimMode::Shoulder][handle % 3]);
+    // Players cast on different frames, alternating between a couple of spells
+    let cast_period = 2 * FPS as u32;
+    let offset = frame + handle as u32 * 13;
+    if offset % cast_period == 0 {
+        let spell = SCRIPTED_SPELLS[(offset / cast_period) as usize % SCRIPTED_SPELLS.len()];
+        input.set_spell(spell);
+        // From uncharged up to fully charged, a step more every cast
+        input.set_charge(((offset / cast_period) % 4) as f32 / 3.0);
+    }
+    // Hold on to each spell for a second before letting go
+    input.set_holding(offset % cast_period < FPS as u32);
+    input
+}
+
+fn run_instance(args: &HarnessArgs, instance: usize, snapshot: &Path) -> bool {
+    let port = |i: usize| args.base_port + i as u16;
+    let players: Vec<String> = (0..args.players)
+        .map(|i| {
+            if i == instance {
+                "localhost".to_owned()
+            } else {
+                format!("127.0.0.1:{}", port(i))
+            }
+        })
+        .collect();
+    let mut settings_args = vec![
+        "--role".to_owned(),
+        "direct".to_owned(),
+        "--players".to_owned(),
+        players.join(","),
+        "--local-player".to_owned(),
+        instance.to_string(),
+        "--local-port".to_owned(),
+        port(instance).to_string(),
+        "--mode".to_owned(),
+        args.mode.to_string(),
+    ];
+    if let Some(relay) = &args.relay {
+        settings_args.extend(["--relay".to_owned(), relay.clone()]);
+    }
+    let settings = ConnectionSettings::load(settings_args);
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("instance {instance}: {e}");
+            return false;
+        }
+    };
+
+    let mut app = headless_app(settings);
+    app.insert_resource(HarnessRun {
+        frame: args.frames,
+        snapshot_path: snapshot.to_owned(),
+        snapshot: None,
+        timeout: args.frames as f32 / FPS as f32 + TIMEOUT_SLACK_SECS,
+    })
+    .add_systems(SaveWorld, take_snapshot)
+    .add_systems(Update, finish_run);
+    crate::add_game_plugins(&mut app);
+    app.run();
+    snapshot.exists()
+}
+
+// Runs every player in one synctest session, so GGRS resimulates each frame and compares checksums,
+// and checks every spell cast comes from its own caster's hands rather than anyone else's.
+fn run_synctest(args: &HarnessArgs) -> bool {
+    let settings = ConnectionSettings::load([
+        "--session".to_owned(),
+        "synctest".to_owned(),
+        "--num-players".to_owned(),
+        args.players.to_string(),
+        "--local-port".to_owned(),
+        args.base_port.to_string(),
+        "--mode".to_owned(),
+        args.mode.to_string(),
+    ]);
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{e}");
+            return false;
+        }
+    };
+
+    let result = Arc::new(Mutex::new(None));
+    let mut app = headless_app(settings);
+    // Anywhere but the origin, so a spell that skips the anchor isn't where it should be
+    app.insert_resource(SharedAnchor(
+        Transform::from_xyz(1.5, 0.0, -2.0).with_rotation(Quat::from_rotation_y(0.6)),
+    ))
+    .insert_resource(SpellOriginCheck {
+        frame: args.frames,
+        timeout: args.frames as f32 / FPS as f32 + TIMEOUT_SLACK_SECS,
+        spells: 0,
+        failures: Vec::new(),
+        result: result.clone(),
+    })
+    .add_systems(
+        GgrsSchedule,
+        check_spell_origins
+            .after(spawn_new_spell_entities)
+            .before(handle_projectile_spells),
+    )
+    .add_systems(Update, finish_synctest);
+    crate::add_game_plugins(&mut app);
+    app.run();
+
+    let result = result.lock().unwrap().take();
+    match result {
+        Some(Ok(spells)) => {
+            println!(
+                "all {spells} spells cast by {} players left their own caster's hands",
+                args.players
+            );
+            true
+        }
+        Some(Err(failures)) => {
+            for failure in failures {
+                eprintln!("{failure}");
+            }
+            false
+        }
+        None => {
+            eprintln!("didn't reach frame {}", args.frames);
+            false
+        }
+    }
+}
+
+fn headless_app(settings: ConnectionSettings) -> App {
+    let mut app = App::new();
+    app.insert_resource(settings)
+        .insert_resource(InputScript(scripted_input))
+        // No window and no GPU, just the simulation
+        .add_plugins(
+            DefaultPlugins
+                .set(RenderPlugin {
+                    render_creation: RenderCreation::Automatic(WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .disable::<WinitPlugin>(),
+        )
+        .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+            1.0 / FPS as f64,
+        )))
+        // The scripted inputs stand in for the headset, but spells still look for hands to hold them
+        .add_systems(Startup, crate::spoof_xr_components);
+    app
+}
+
+#[derive(Resource)]
+struct SpellOriginCheck {
+    // Frame the session runs to
+    frame: i32,
+    timeout: f32,
+    // Spells checked so far, counting resimulated frames again
+    spells: usize,
+    failures: Vec<String>,
+    // Read once the app's finished, it's gone with the app otherwise
+    result: Arc<Mutex<Option<Result<usize, Vec<String>>>>>,
+}
+
+// Runs between a spell being cast and it being handled, while its `SpellObj` is still where it was cast from.
+// It has to be where its caster's own input aims it, and between its caster's own palms.
+fn check_spell_origins(
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    registry: Res<SpellRegistry>,
+    spells: Query<(&Transform, &PlayerID), With<SpellObj>>,
+    bosses: Query<&Transform, With<Boss>>,
+    left_palms: Query<(&Transform, &PlayerID), With<PlayerLeftPalm>>,
+    right_palms: Query<(&Transform, &PlayerID), With<PlayerRightPalm>>,
+    anchor: Res<SharedAnchor>,
+    mut check: ResMut<SpellOriginCheck>,
+) {
+    for (t, caster) in spells.iter() {
+        let input = inputs[caster.handle].0;
+        // The scripted spells are all handled on the frame they're cast, so this one was cast just now
+        let Some(spell) = registry.get(input.spell()) else {
+            continue;
+        };
+        check.spells += 1;
+
+        let expected = aim_spell(
+            &input,
+            &anchor.0,
+            bosses.iter().map(|b| b.translation),
+            spell.aim_assist_degrees,
+        );
+        if t.translation.distance(expected.translation) > SPELL_ORIGIN_TOLERANCE
+            || t.rotation.angle_between(expected.rotation) > SPELL_AIM_TOLERANCE
+        {
+            let failure = format!(
+                "frame {}: player {}'s {} was cast from {:?} facing {:?}, but their input puts it at {:?} facing {:?}",
+                **frame, caster.handle, spell.name, t.translation, t.rotation, expected.translation, expected.rotation
+            );
+            check.failures.push(failure);
+        }
+
+        let left = left_palms.iter().find(|(_, p)| p.handle == caster.handle);
+        let right = right_palms.iter().find(|(_, p)| p.handle == caster.handle);
+        let (Some((left, _)), Some((right, _))) = (left, right) else {
+            let failure = format!("frame {}: player {} has no palms", **frame, caster.handle);
+            check.failures.push(failure);
+            continue;
+        };
+        let palms = left.translation.lerp(right.translation, 0.5);
+        if t.translation.distance(palms) > SPELL_ORIGIN_TOLERANCE {
+            let failure = format!(
+                "frame {}: player {}'s {} was cast from {:?}, but their palms are around {palms:?}",
+                **frame, caster.handle, spell.name, t.translation
+            );
+            check.failures.push(failure);
+        }
+    }
+}
+
+fn finish_synctest(
+    time: Res<Time<Real>>,
+    frame: Option<Res<RollbackFrameCount>>,
+    mut check: ResMut<SpellOriginCheck>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // Nothing's been simulated before the session starts
+    let frame = frame.map_or(0, |f| **f);
+    if frame < check.frame && time.elapsed_seconds() <= check.timeout {
+        return;
+    }
+    let result = if frame < check.frame {
+        None
+    } else if !check.failures.is_empty() {
+        Some(Err(std::mem::take(&mut check.failures)))
+    } else if check.spells == 0 {
+        Some(Err(vec![format!(
+            "nobody cast a spell in {} frames",
+            check.frame
+        )]))
+    } else {
+        Some(Ok(check.spells))
+    };
+    *check.result.lock().unwrap() = result;
+    exit.send(AppExit);
+}
+
+#[derive(Resource)]
+struct HarnessRun {
+    // Frame every instance's state is compared at
+    frame: i32,
+    snapshot_path: PathBuf,
+    // Resimulating the frame replaces the snapshot, so it's only final once the frame is confirmed
+    snapshot: Option<String>,
+    timeout: f32,
+}
+
+// Everything that has to match across instances. Entities are listed by what they are rather than their id,
+// which can differ between instances, and sorted so they're in the same order everywhere.
+fn take_snapshot(
+    frame: Res<RollbackFrameCount>,
+    mut run: ResMut<HarnessRun>,
+    boss: Query<(&Transform, &BossHealth), With<Boss>>,
+    heads: Query<(&Transform, &PlayerID), With<PlayerHead>>,
+    left_palms: Query<(&Transform, &PlayerID), With<PlayerLeftPalm>>,
+    right_palms: Query<(&Transform, &PlayerID), With<PlayerRightPalm>>,
+    spells: Query<(Option<&Transform>, &PlayerID), With<SpellObj>>,
+    player_health: Query<(&PlayerHealth, &PlayerID)>,
+    player_mana: Query<(&Mana, &PlayerID)>,
+    // Only there in a duel
+    score: Option<Res<DuelScore>>,
+    round: Option<Res<RoundState>>,
+) {
+    if **frame != run.frame {
+        return;
+    }
+    let mut lines = Vec::new();
+    for (t, health) in boss.iter() {
+        lines.push(format!(
+            "boss at {:?} with {:?}/{:?} health",
+            t.translation, health.current, health.max
+        ));
+    }
+    player_lines(&mut lines, "head", heads.iter());
+    player_lines(&mut lines, "left palm", left_palms.iter());
+    player_lines(&mut lines, "right palm", right_palms.iter());
+    for (t, p) in spells.iter() {
+        lines.push(format!(
+            "spell of player {} at {:?}",
+            p.handle,
+            t.map(|t| t.translation)
+        ));
+    }
+    for (health, p) in player_health.iter() {
+        lines.push(format!(
+            "player {} with {:?}/{:?} health",
+            p.handle, health.current, health.max
+        ));
+    }
+    for (mana, p) in player_mana.iter() {
+        lines.push(format!(
+            "player {} with {:?} mana, cooldowns {:?}",
+            p.handle, mana.current, mana.cooldowns
+        ));
+    }
+    if let (Some(score), Some(round)) = (score, round) {
+        lines.push(format!("duel score {:?} in {:?}", *score, *round));
+    }
+    lines.sort();
+
+    let mut snapshot = String::new();
+    for line in lines {
+        let _ = writeln!(snapshot, "{line}");
+    }
+    run.snapshot = Some(snapshot);
+}
+
+fn player_lines<'a>(
+    lines: &mut Vec<String>,
+    name: &str,
+    players: impl Iterator<Item = (&'a Transform, &'a PlayerID)>,
+) {
+    for (t, p) in players {
+        lines.push(format!(
+            "player {} {name} at {:?} facing {:?}",
+            p.handle, t.translation, t.rotation
+        ));
+    }
+}
+
+fn finish_run(
+    time: Res<Time<Real>>,
+    mut run: ResMut<HarnessRun>,
+    session: Option<Res<Session<WizGgrsConfig>>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if time.elapsed_seconds() > run.timeout {
+        error!("timed out before frame {} was confirmed", run.frame);
+        exit.send(AppExit);
+        return;
+    }
+    let Some(Session::P2P(session)) = session.as_deref() else {
+        return;
+    };
+    let confirmed = session.confirmed_frame();
+    if confirmed < run.frame {
+        return;
+    }
+    if let Some(snapshot) = run.snapshot.take() {
+        if let Err(e) = std::fs::write(&run.snapshot_path, snapshot) {
+            error!("failed to write {}: {e}", run.snapshot_path.display());
+            exit.send(AppExit);
+        }
+    }
+    if confirmed >= run.frame + LINGER_FRAMES {
+        exit.send(AppExit);
+    }
+}
@@ -1,7 +1,11 @@
 use bevy::{math::primitives, prelude::*};
 use bevy_oxr::xr_input::trackers::{OpenXRLeftEye, OpenXRRightEye};
 
-use crate::{boss::BossHealth, projectile::DamageMask};
+use crate::{
+    boss::BossHealth,
+    duel::PlayerHealth,
+    network::{LocalPlayerID, PlayerID},
+};
 
 pub struct HealthBarPlugin;
 
@@ -60,6 +64,8 @@ fn spawn_health_bar(
 
 fn update_health_bar(
     health_query: Query<&BossHealth>,
+    player_health: Query<(&PlayerHealth, &PlayerID)>,
+    local_player: Option<Res<LocalPlayerID>>,
     mut health_bar_bg_query: Query<&mut Transform, (With<HealthBarBackground>, Without<HealthBar>)>,
 
     mut health_bar_query: Query<&mut Transform, (Without<HealthBarBackground>, With<HealthBar>)>,
@@ -86,13 +92,16 @@ fn update_health_bar(
     let head_pos = left_eye.translation.lerp(right_eye.translation, 0.5);
     let head_rot = left_eye.rotation;
 
+    // Without a boss there's a duel on, so show our own health instead
     let health = match health_query.get_single() {
-        Ok(h) => h,
-        Err(_) => &BossHealth {
-            max: 1.,
-            current: 0.,
-            damage_mask: DamageMask(0),
-        },
+        Ok(h) => h.normalized_value(),
+        Err(_) => local_player
+            .and_then(|local| {
+                player_health
+                    .iter()
+                    .find(|(_, id)| id.handle == local.handle)
+            })
+            .map_or(0., |(h, _)| h.normalized_value()),
     };
 
     let mut health_bar_bg_transform = health_bar_bg_query.get_single_mut().unwrap();
@@ -109,5 +118,5 @@ fn update_health_bar(
 
     let mut health_bar_transform = health_bar_query.get_single_mut().unwrap();
 
-    health_bar_transform.scale = Vec3::new(health.normalized_value() * HEALTHBAR_WIDTH, 0.6, 1.0);
+    health_bar_transform.scale = Vec3::new(health * HEALTHBAR_WIDTH, 0.6, 1.0);
 }
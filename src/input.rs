@@ -0,0 +1,495 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::network::AimMode;
+
+// Positions are sent as whole millimetres relative to the shared anchor, which covers +-32m.
+const POSITION_SCALE: f32 = 1000.0;
+// Furthest a position component can move when quantized, in metres.
+pub(crate) const POSITION_PRECISION: f32 = 0.5 / POSITION_SCALE;
+
+// Rotations are sent as the three smallest components of the quaternion, the fourth follows from them being unit length.
+// Each gets this many bits, the remaining two say which component was left out.
+const ROTATION_BITS: u32 = 10;
+const ROTATION_MASK: u32 = (1 << ROTATION_BITS) - 1;
+const ROTATION_STEPS: f32 = ((1 << (ROTATION_BITS - 1)) - 1) as f32;
+// Anything but the largest component of a unit quaternion is within +-1/sqrt(2).
+const ROTATION_RANGE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+// Largest angle between a rotation and its quantized version, in radians.
+pub(crate) const ROTATION_PRECISION: f32 = 0.01;
+
+// The spell cast this frame takes the low bits of the spell field, 0 for none.
+// The rest are free for flags.
+const SPELL_BITS: u32 = 4;
+const SPELL_MASK: u16 = (1 << SPELL_BITS) - 1;
+// Highest wire id a spell can have.
+pub const MAX_SPELL_ID: u32 = SPELL_MASK as u32;
+// Set when the input carries the finger joints of both hands.
+const HAND_SKELETON_FLAG: u16 = 1 << SPELL_BITS;
+// The two bits after it are how the player aims, so every peer points their spells the same way.
+const AIM_SHIFT: u32 = SPELL_BITS + 1;
+const AIM_MASK: u16 = 0b11 << AIM_SHIFT;
+// Then how long the spell was charged for, in steps from none to full.
+const CHARGE_SHIFT: u32 = AIM_SHIFT + 2;
+const CHARGE_BITS: u32 = 4;
+const CHARGE_STEPS: u16 = (1 << CHARGE_BITS) - 1;
+const CHARGE_MASK: u16 = CHARGE_STEPS << CHARGE_SHIFT;
+// Set while the player's fingertips are apart, which is how a spell they've thrown is kept hold of.
+const HOLDING_FLAG: u16 = 1 << (CHARGE_SHIFT + CHARGE_BITS);
+
+// Number of finger joints sent per hand, `network::finger_joints` has the order they're in.
+pub const HAND_JOINTS: usize = 19;
+// Finger joints are sent relative to the palm in 2mm steps, which reaches 25cm from it.
+const JOINT_SCALE: f32 = 500.0;
+// Furthest a finger joint can move when quantized, in metres.
+const JOINT_PRECISION: f32 = 0.5 / JOINT_SCALE * 1.7321;
+
+// What every player sends every frame, quantized to keep packets small: 148 bytes, most of them finger joints.
+// All positions and rotations are relative to the shared anchor.
+// An all zero input has everything at the anchor with no rotation and no spell.
+// GGRS sends each input as a run length encoded difference from an earlier one,
+// so the finger joints cost next to nothing while they're left zeroed.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Pod, Zeroable, Default)]
+pub struct PlayerInput {
+    head_pos: [i16; 3],
+    left_hand_pos: [i16; 3],
+    right_hand_pos: [i16; 3],
+    spell: u16,
+    head_rot: u32,
+    left_hand_rot: u32,
+    right_hand_rot: u32,
+    left_hand_joints: [[i8; 3]; HAND_JOINTS],
+    right_hand_joints: [[i8; 3]; HAND_JOINTS],
+    _padding: [u8; 2],
+}
+
+const _: () = assert!(std::mem::size_of::<PlayerInput>() == 148);
+
+impl PlayerInput {
+    pub fn head_pos(&self) -> Vec3 {
+        decode_position(self.head_pos)
+    }
+
+    pub fn head_rot(&self) -> Quat {
+        decode_rotation(self.head_rot)
+    }
+
+    pub fn left_hand_pos(&self) -> Vec3 {
+        decode_position(self.left_hand_pos)
+    }
+
+    pub fn left_hand_rot(&self) -> Quat {
+        decode_rotation(self.left_hand_rot)
+    }
+
+    pub fn right_hand_pos(&self) -> Vec3 {
+        decode_position(self.right_hand_pos)
+    }
+
+    pub fn right_hand_rot(&self) -> Quat {
+        decode_rotation(self.right_hand_rot)
+    }
+
+    pub fn spell(&self) -> u32 {
+        (self.spell & SPELL_MASK) as u32
+    }
+
+    pub fn set_head(&mut self, pos: Vec3, rot: Quat) {
+        self.head_pos = encode_position(pos);
+        self.head_rot = encode_rotation(rot);
+    }
+
+    pub fn set_left_hand(&mut self, pos: Vec3, rot: Quat) {
+        self.left_hand_pos = encode_position(pos);
+        self.left_hand_rot = encode_rotation(rot);
+    }
+
+    pub fn set_right_hand(&mut self, pos: Vec3, rot: Quat) {
+        self.right_hand_pos = encode_position(pos);
+        self.right_hand_rot = encode_rotation(rot);
+    }
+
+    // The same input with the player standing somewhere else, finger joints move along with the palms.
+    pub fn translated(&self, offset: Vec3) -> Self {
+        let mut input = *self;
+        input.head_pos = encode_position(self.head_pos() + offset);
+        input.left_hand_pos = encode_position(self.left_hand_pos() + offset);
+        input.right_hand_pos = encode_position(self.right_hand_pos() + offset);
+        input
+    }
+
+    // None if the player isn't sending their finger joints.
+    pub fn left_hand_joints(&self) -> Option<[Vec3; HAND_JOINTS]> {
+        self.has_hand_joints().then(|| {
+            decode_joints(
+                self.left_hand_pos(),
+                self.left_hand_rot(),
+                &self.left_hand_joints,
+            )
+        })
+    }
+
+    pub fn right_hand_joints(&self) -> Option<[Vec3; HAND_JOINTS]> {
+        self.has_hand_joints().then(|| {
+            decode_joints(
+                self.right_hand_pos(),
+                self.right_hand_rot(),
+                &self.right_hand_joints,
+            )
+        })
+    }
+
+    fn has_hand_joints(&self) -> bool {
+        self.spell & HAND_SKELETON_FLAG != 0
+    }
+
+    // Joints are stored relative to the palms, so set those first.
+    pub fn set_hand_joints(&mut self, left: &[Vec3; HAND_JOINTS], right: &[Vec3; HAND_JOINTS]) {
+        self.left_hand_joints = encode_joints(self.left_hand_pos(), self.left_hand_rot(), left);
+        self.right_hand_joints = encode_joints(self.right_hand_pos(), self.right_hand_rot(), right);
+        self.spell |= HAND_SKELETON_FLAG;
+    }
+
+    pub fn aim_mode(&self) -> AimMode {
+        match (self.spell & AIM_MASK) >> AIM_SHIFT {
+            1 => AimMode::Palms,
+            2 => AimMode::Shoulder,
+            _ => AimMode::Head,
+        }
+    }
+
+    pub fn set_aim_mode(&mut self, mode: AimMode) {
+        let bits: u16 = match mode {
+            AimMode::Head => 0,
+            AimMode::Palms => 1,
+            AimMode::Shoulder => 2,
+        };
+        self.spell = (self.spell & !AIM_MASK) | (bits << AIM_SHIFT);
+    }
+
+    // From 0 for an uncharged spell to 1 for a fully charged one.
+    pub fn charge(&self) -> f32 {
+        ((self.spell & CHARGE_MASK) >> CHARGE_SHIFT) as f32 / CHARGE_STEPS as f32
+    }
+
+    pub fn set_charge(&mut self, charge: f32) {
+        let steps = (charge.clamp(0.0, 1.0) * CHARGE_STEPS as f32).round() as u16;
+        self.spell = (self.spell & !CHARGE_MASK) | (steps << CHARGE_SHIFT);
+    }
+
+    // Whether the player's still holding on to what they last cast, so it can be steered until they let go.
+    pub fn holding(&self) -> bool {
+        self.spell & HOLDING_FLAG != 0
+    }
+
+    pub fn set_holding(&mut self, holding: bool) {
+        if holding {
+            self.spell |= HOLDING_FLAG;
+        } else {
+            self.spell &= !HOLDING_FLAG;
+        }
+    }
+
+    pub fn set_spell(&mut self, spell: u32) {
+        debug_assert!(
+            spell <= SPELL_MASK as u32,
+            "spell {spell} doesn't fit in {SPELL_BITS} bits"
+        );
+        self.spell = (self.spell & !SPELL_MASK) | (spell as u16 & SPELL_MASK);
+    }
+}
+
+// Shows the decoded values, the raw ones don't mean much when reading a desync report
+impl fmt::Debug for PlayerInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlayerInput")
+            .field("head_pos", &self.head_pos())
+            .field("head_rot", &self.head_rot())
+            .field("left_hand_pos", &self.left_hand_pos())
+            .field("left_hand_rot", &self.left_hand_rot())
+            .field("right_hand_pos", &self.right_hand_pos())
+            .field("right_hand_rot", &self.right_hand_rot())
+            .field("spell", &self.spell())
+            .field("aim_mode", &self.aim_mode())
+            .field("charge", &self.charge())
+            .field("left_hand_joints", &self.left_hand_joints())
+            .field("right_hand_joints", &self.right_hand_joints())
+            .finish()
+    }
+}
+
+// Out of range positions are clamped to the edge of the range.
+fn encode_position(pos: Vec3) -> [i16; 3] {
+    let encoded = pos.to_array().map(|v| {
+        (v * POSITION_SCALE)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    });
+    debug_assert!(
+        pos.abs().max_element() * POSITION_SCALE > i16::MAX as f32
+            || (decode_position(encoded) - pos).abs().max_element() <= POSITION_PRECISION * 1.01,
+        "{pos} was quantized to {}",
+        decode_position(encoded)
+    );
+    encoded
+}
+
+fn decode_position(pos: [i16; 3]) -> Vec3 {
+    Vec3::from_array(pos.map(|v| v as f32 / POSITION_SCALE))
+}
+
+fn encode_rotation(rot: Quat) -> u32 {
+    let components = rot.normalize().to_array();
+    let largest = (0..4)
+        .max_by(|&a, &b| components[a].abs().total_cmp(&components[b].abs()))
+        .unwrap();
+    // q and -q are the same rotation, so flip it to make the one we leave out positive
+    let sign = components[largest].signum();
+
+    // Stored as 3 - index so that all zeros decodes to the identity
+    let mut encoded = (3 - largest) as u32;
+    for i in (0..4).filter(|&i| i != largest) {
+        let v = (components[i] * sign / ROTATION_RANGE * ROTATION_STEPS)
+            .round()
+            .clamp(-ROTATION_STEPS, ROTATION_STEPS) as i32;
+        encoded = (encoded << ROTATION_BITS) | (v as u32 & ROTATION_MASK);
+    }
+    debug_assert!(
+        decode_rotation(encoded).angle_between(rot.normalize()) <= ROTATION_PRECISION,
+        "{rot} was quantized to {}",
+        decode_rotation(encoded)
+    );
+    encoded
+}
+
+fn decode_rotation(rot: u32) -> Quat {
+    let largest = 3 - (rot >> (3 * ROTATION_BITS)) as usize;
+    let mut components = [0.0; 4];
+    for (n, i) in (0..4).filter(|&i| i != largest).enumerate() {
+        let bits = (rot >> ((2 - n as u32) * ROTATION_BITS)) & ROTATION_MASK;
+        // Sign extend the component back to 32 bits
+        let v = ((bits << (32 - ROTATION_BITS)) as i32) >> (32 - ROTATION_BITS);
+        components[i] = v as f32 / ROTATION_STEPS * ROTATION_RANGE;
+    }
+    let rest: f32 = components.iter().map(|c| c * c).sum();
+    components[largest] = (1.0 - rest).max(0.0).sqrt();
+    Quat::from_array(components)
+}
+
+// Joints outside the range are clamped to the edge of it.
+fn encode_joints(
+    palm_pos: Vec3,
+    palm_rot: Quat,
+    joints: &[Vec3; HAND_JOINTS],
+) -> [[i8; 3]; HAND_JOINTS] {
+    let to_palm = palm_rot.inverse();
+    let local = joints.map(|joint| to_palm * (joint - palm_pos));
+    let encoded = local.map(|joint| {
+        joint.to_array().map(|v| {
+            (v * JOINT_SCALE)
+                .round()
+                .clamp(i8::MIN as f32, i8::MAX as f32) as i8
+        })
+    });
+    debug_assert!(
+        decode_joints(palm_pos, palm_rot, &encoded)
+            .iter()
+            .zip(joints.iter().zip(local.iter()))
+            .all(|(decoded, (joint, local))| {
+                local.abs().max_element() * JOINT_SCALE > i8::MAX as f32
+                    || decoded.distance(*joint) <= JOINT_PRECISION * 1.01
+            }),
+        "finger joints weren't quantized precisely enough"
+    );
+    encoded
+}
+
+fn decode_joints(
+    palm_pos: Vec3,
+    palm_rot: Quat,
+    joints: &[[i8; 3]; HAND_JOINTS],
+) -> [Vec3; HAND_JOINTS] {
+    joints
+        .map(|joint| palm_pos + palm_rot * Vec3::from_array(joint.map(|v| v as f32 / JOINT_SCALE)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    // Largest position that still fits in an i16 of millimetres.
+    const POSITION_EDGE: f32 = i16::MAX as f32 / POSITION_SCALE;
+    // Largest finger joint offset from the palm that still fits in an i8.
+    const JOINT_EDGE: f32 = i8::MAX as f32 / JOINT_SCALE;
+
+    fn rotations() -> Vec<Quat> {
+        let axes = [
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+            Vec3::new(1.0, 1.0, 0.0).normalize(),
+            Vec3::new(-0.3, 0.8, 0.5).normalize(),
+            Vec3::ONE.normalize(),
+        ];
+        let angles = [0.0, 1e-4, -1e-4, 0.3, PI / 2.0, 2.0, PI - 1e-4, PI, -PI];
+        let mut rotations = Vec::new();
+        for axis in axes {
+            for angle in angles {
+                let rot = Quat::from_axis_angle(axis, angle);
+                // -q is the same rotation, but with w the other way round
+                rotations.push(rot);
+                rotations.push(-rot);
+            }
+        }
+        rotations
+    }
+
+    #[test]
+    fn positions_round_trip_within_precision() {
+        let values = [
+            0.0,
+            0.0004,
+            -0.0006,
+            1.2345,
+            -7.8912,
+            POSITION_EDGE,
+            -POSITION_EDGE,
+            POSITION_EDGE - 0.0004,
+            -POSITION_EDGE + 0.0004,
+        ];
+        for x in values {
+            for y in values {
+                let pos = Vec3::new(x, y, -x);
+                let decoded = decode_position(encode_position(pos));
+                assert!(
+                    (decoded - pos).abs().max_element() <= POSITION_PRECISION * 1.01,
+                    "{pos} came back as {decoded}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn positions_out_of_range_are_clamped() {
+        let decoded = decode_position(encode_position(Vec3::new(40.0, -40.0, 0.0)));
+        assert_eq!(
+            decoded,
+            Vec3::new(POSITION_EDGE, i16::MIN as f32 / POSITION_SCALE, 0.0)
+        );
+    }
+
+    #[test]
+    fn rotations_round_trip_within_precision() {
+        for rot in rotations() {
+            let decoded = decode_rotation(encode_rotation(rot));
+            assert!(
+                decoded.angle_between(rot) <= ROTATION_PRECISION,
+                "{rot} came back as {decoded}, {} radians off",
+                decoded.angle_between(rot)
+            );
+            assert!(decoded.is_normalized(), "{decoded} isn't unit length");
+        }
+    }
+
+    #[test]
+    fn zeroed_input_is_at_the_anchor() {
+        let input = PlayerInput::default();
+        assert_eq!(input.head_pos(), Vec3::ZERO);
+        assert_eq!(input.head_rot(), Quat::IDENTITY);
+        assert_eq!(input.left_hand_rot(), Quat::IDENTITY);
+        assert_eq!(input.spell(), 0);
+        assert_eq!(input.aim_mode(), AimMode::Head);
+        assert_eq!(input.charge(), 0.0);
+        assert!(!input.holding());
+        assert!(input.left_hand_joints().is_none());
+    }
+
+    #[test]
+    fn spell_flags_are_kept_apart() {
+        let aims = [AimMode::Head, AimMode::Palms, AimMode::Shoulder];
+        for spell in 0..=MAX_SPELL_ID {
+            for aim in aims {
+                for step in 0..=CHARGE_STEPS {
+                    let charge = step as f32 / CHARGE_STEPS as f32;
+                    let mut input = PlayerInput::default();
+                    input.set_hand_joints(&[Vec3::ZERO; HAND_JOINTS], &[Vec3::ZERO; HAND_JOINTS]);
+                    input.set_holding(true);
+                    input.set_charge(charge);
+                    input.set_aim_mode(aim);
+                    input.set_spell(spell);
+                    assert_eq!(input.spell(), spell);
+                    assert_eq!(input.aim_mode(), aim);
+                    assert_eq!(input.charge(), charge);
+                    assert!(input.has_hand_joints());
+                    assert!(input.holding());
+
+                    // Setting one again leaves the others alone
+                    input.set_spell(0);
+                    input.set_holding(false);
+                    assert_eq!(input.aim_mode(), aim);
+                    assert_eq!(input.charge(), charge);
+                    assert!(input.has_hand_joints());
+                    assert!(!input.holding());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn charge_is_clamped() {
+        let mut input = PlayerInput::default();
+        input.set_charge(2.0);
+        assert_eq!(input.charge(), 1.0);
+        input.set_charge(-1.0);
+        assert_eq!(input.charge(), 0.0);
+    }
+
+    #[test]
+    fn finger_joints_round_trip_within_precision() {
+        let offsets = [
+            Vec3::ZERO,
+            Vec3::new(0.01, -0.02, 0.03),
+            Vec3::splat(JOINT_EDGE),
+            Vec3::splat(-JOINT_EDGE),
+            Vec3::new(JOINT_EDGE, -JOINT_EDGE, 0.1),
+        ];
+        let palm_pos = Vec3::new(0.3, 1.1, -0.4);
+        for palm_rot in rotations() {
+            let joints: [Vec3; HAND_JOINTS] =
+                std::array::from_fn(|i| palm_pos + palm_rot * offsets[i % offsets.len()]);
+            let decoded = decode_joints(
+                palm_pos,
+                palm_rot,
+                &encode_joints(palm_pos, palm_rot, &joints),
+            );
+            for (joint, decoded) in joints.iter().zip(decoded.iter()) {
+                assert!(
+                    decoded.distance(*joint) <= JOINT_PRECISION * 1.01,
+                    "{joint} came back as {decoded}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn finger_joints_follow_the_decoded_palm() {
+        let mut input = PlayerInput::default();
+        let rot = Quat::from_rotation_y(1.0);
+        input.set_left_hand(Vec3::new(-0.2, 1.0, -0.3), rot);
+        input.set_right_hand(Vec3::new(0.2, 1.0, -0.3), rot);
+        let left = [input.left_hand_pos() + rot * Vec3::new(0.0, 0.0, -0.1); HAND_JOINTS];
+        let right = [input.right_hand_pos() + rot * Vec3::new(0.05, 0.0, -0.1); HAND_JOINTS];
+        input.set_hand_joints(&left, &right);
+        for (joint, decoded) in left.iter().zip(input.left_hand_joints().unwrap().iter()) {
+            assert!(decoded.distance(*joint) <= JOINT_PRECISION * 1.01);
+        }
+        for (joint, decoded) in right.iter().zip(input.right_hand_joints().unwrap().iter()) {
+            assert!(decoded.distance(*joint) <= JOINT_PRECISION * 1.01);
+        }
+    }
+}
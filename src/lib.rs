@@ -3,12 +3,19 @@
 
 mod assets;
 mod boss;
+pub mod duel;
+#[cfg(not(target_os = "android"))]
+pub mod harness;
 mod health_bar;
+mod input;
+mod mana;
 mod network;
 mod player;
 mod projectile;
+pub mod relay;
 mod speech;
 mod spell_control;
+mod spell_registry;
 mod spells;
 mod xr;
 
@@ -26,25 +33,12 @@ use bevy_oxr::xr_input::hands::common::OpenXrHandInput;
 #[cfg(target_os = "android")]
 use bevy_oxr::{DefaultXrPlugins, OpenXrPlugin};
 use bevy_xpbd_3d::prelude::*;
-use bytemuck::{Pod, Zeroable};
 
 const FPS: usize = 72;
 
-pub type WizGgrsConfig = GgrsConfig<PlayerInput>;
+pub use input::PlayerInput;
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable, Default)]
-pub struct PlayerInput {
-    head_pos: Vec3,
-    spell: u32,
-    left_hand_pos: Vec3,
-    _padding0: u32,
-    right_hand_pos: Vec3,
-    _padding1: u32,
-    head_rot: Quat,
-    left_hand_rot: Quat,
-    right_hand_rot: Quat,
-}
+pub type WizGgrsConfig = GgrsConfig<PlayerInput>;
 
 #[derive(PhysicsLayer)]
 enum PhysLayer {
@@ -59,19 +53,24 @@ enum PhysLayer {
 
 #[bevy_main]
 pub fn main() {
+    let settings = match load_connection_settings() {
+        Ok(settings) => settings,
+        Err(network::SettingsError::HelpRequested) => {
+            println!("{}", network::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
     let mut app = App::new();
-    app.add_systems(Startup, setup)
-        .add_plugins(LogDiagnosticsPlugin::default())
+    app.insert_resource(settings);
+    app.add_plugins(LogDiagnosticsPlugin::default())
         .add_plugins(FrameTimeDiagnosticsPlugin)
-        .add_plugins(PhysicsPlugins::default())
-        .add_plugins(assets::AssetHandlesPlugin)
-        .add_plugins(boss::BossPlugin)
-        .add_plugins(network::NetworkPlugin)
-        .add_plugins(projectile::ProjectilePlugin)
-        .add_plugins(speech::SpeechPlugin)
-        .add_plugins(spell_control::SpellControlPlugin)
-        .add_plugins(spells::SpellsPlugin)
-        .add_plugins(health_bar::HealthBarPlugin);
+        .add_plugins(speech::SpeechPlugin);
+    add_game_plugins(&mut app);
 
     #[cfg(target_os = "android")]
     {
@@ -128,6 +127,49 @@ pub fn main() {
     app.run();
 }
 
+// Everything that makes up the game itself, shared with the headless test harness.
+// Speech recognition needs a microphone, so it's left to whoever calls this.
+fn add_game_plugins(app: &mut App) {
+    // Players either fight the boss together or each other, never both
+    let mode = app
+        .world
+        .get_resource::<network::ConnectionSettings>()
+        .map(|s| s.mode)
+        .unwrap_or_default();
+    match mode {
+        network::GameMode::Boss => app.add_plugins(boss::BossPlugin),
+        network::GameMode::Duel => app.add_plugins(duel::DuelPlugin),
+    };
+    app.add_systems(Startup, setup)
+        .add_plugins(PhysicsPlugins::default())
+        .add_plugins(assets::AssetHandlesPlugin)
+        .add_plugins(network::NetworkPlugin)
+        .add_plugins(network::NetworkStatsPlugin)
+        .add_plugins(projectile::ProjectilePlugin)
+        .add_plugins(spell_control::SpellControlPlugin)
+        .add_plugins(mana::ManaPlugin)
+        .add_plugins(spells::SpellsPlugin)
+        .add_plugins(health_bar::HealthBarPlugin);
+}
+
+#[cfg(not(target_os = "android"))]
+fn load_connection_settings() -> Result<network::ConnectionSettings, network::SettingsError> {
+    network::ConnectionSettings::load(std::env::args().skip(1))
+}
+
+// There's no command line on the headset, so only look for a config file next to the vosk model
+#[cfg(target_os = "android")]
+fn load_connection_settings() -> Result<network::ConnectionSettings, network::SettingsError> {
+    let path = std::path::Path::new(
+        "/storage/emulated/0/Android/data/com.github.goudanough.wizards/files/wizards.ron",
+    );
+    if path.exists() {
+        network::ConnectionSettings::from_file(path)
+    } else {
+        Ok(network::ConnectionSettings::default())
+    }
+}
+
 #[derive(Component)]
 struct PancakeCamera;
 
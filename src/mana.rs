@@ -0,0 +1,199 @@
+use std::{
+    fmt::Write as _,
+    hash::{Hash, Hasher},
+};
+
+use bevy::prelude::*;
+use bevy_ggrs::GgrsSchedule;
+
+use crate::{
+    input::MAX_SPELL_ID,
+    network::{
+        move_networked_player_objs, spawn_wrist_panel, LocalPlayerID, MatchState, PlayerHead,
+        PlayerID, RollbackChecksumApp, StableHasher,
+    },
+    spell_registry::{SpellDef, SpellRegistry},
+    FPS,
+};
+
+const MAX_MANA: f32 = 100.0;
+const MANA_REGEN_PER_SEC: f32 = 10.0;
+// How long the caster is told about a spell that didn't go off.
+const FAILED_CAST_FRAMES: u32 = FPS as u32;
+const PANEL_SIZE: Vec2 = Vec2::new(0.12, 0.08);
+// Just above the back of the wrist, towards the elbow.
+const PANEL_OFFSET: Vec3 = Vec3::new(0.0, 0.03, 0.04);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CastFailure {
+    NotEnoughMana,
+    CoolingDown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FailedCast {
+    pub spell: u32,
+    pub reason: CastFailure,
+    frames_left: u32,
+}
+
+// What every player has to spend on spells. It lives on their head, alongside their `PlayerID`.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Mana {
+    pub current: f32,
+    // Frames until each spell, indexed by wire id, can be cast again
+    pub cooldowns: [u32; MAX_SPELL_ID as usize + 1],
+    // The last cast that didn't go off, while the caster's still being told about it
+    pub failed: Option<FailedCast>,
+}
+
+impl Mana {
+    pub fn full() -> Self {
+        Self {
+            current: MAX_MANA,
+            cooldowns: [0; MAX_SPELL_ID as usize + 1],
+            failed: None,
+        }
+    }
+
+    // Pays for the spell and starts its cooldown, unless it can't be cast yet.
+    pub fn try_cast(&mut self, spell: &SpellDef) -> Result<(), CastFailure> {
+        let result = if self.cooldowns[spell.id as usize] > 0 {
+            Err(CastFailure::CoolingDown)
+        } else if self.current < spell.mana_cost {
+            Err(CastFailure::NotEnoughMana)
+        } else {
+            self.current -= spell.mana_cost;
+            self.cooldowns[spell.id as usize] = (spell.cooldown_secs * FPS as f32).round() as u32;
+            Ok(())
+        };
+        if let Err(reason) = result {
+            self.failed = Some(FailedCast {
+                spell: spell.id,
+                reason,
+                frames_left: FAILED_CAST_FRAMES,
+            });
+        }
+        result
+    }
+}
+
+#[derive(Component, Clone)]
+struct ManaPanel;
+
+#[derive(Component)]
+struct ManaPanelText;
+
+pub struct ManaPlugin;
+
+impl Plugin for ManaPlugin {
+    fn build(&self, app: &mut App) {
+        app.rollback_component_with_copy::<Mana>()
+            .checksum_rollback_component::<Mana>(|m| {
+                let mut hasher = StableHasher::default();
+                m.current.to_bits().hash(&mut hasher);
+                m.cooldowns.hash(&mut hasher);
+                m.failed.hash(&mut hasher);
+                hasher.finish()
+            })
+            .add_systems(
+                Update,
+                (
+                    spawn_mana_panel.run_if(resource_exists::<LocalPlayerID>),
+                    show_mana,
+                ),
+            )
+            // Casting checks every player's mana, so it has to be rolled back with everything else
+            .add_systems(
+                GgrsSchedule,
+                (give_players_mana, regen_mana)
+                    .chain()
+                    .after(move_networked_player_objs)
+                    .run_if(resource_equals(MatchState::Running)),
+            );
+    }
+}
+
+// Players join with full mana, including ones that come back after a restart.
+fn give_players_mana(
+    mut commands: Commands,
+    heads: Query<Entity, (With<PlayerHead>, Without<Mana>)>,
+) {
+    for e in heads.iter() {
+        commands.entity(e).insert(Mana::full());
+    }
+}
+
+pub fn regen_mana(mut manas: Query<&mut Mana>) {
+    for mut mana in manas.iter_mut() {
+        mana.current = (mana.current + MANA_REGEN_PER_SEC / FPS as f32).min(MAX_MANA);
+        for cooldown in mana.cooldowns.iter_mut() {
+            *cooldown = cooldown.saturating_sub(1);
+        }
+        mana.failed = mana.failed.and_then(|f| {
+            (f.frames_left > 1).then_some(FailedCast {
+                frames_left: f.frames_left - 1,
+                ..f
+            })
+        });
+    }
+}
+
+// Only our own mana is shown, spectators don't get a panel.
+fn spawn_mana_panel(
+    mut commands: Commands,
+    panels: Query<(), With<ManaPanel>>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !panels.is_empty() {
+        return;
+    }
+    let text = spawn_wrist_panel(
+        &mut commands,
+        &mut images,
+        &mut meshes,
+        &mut materials,
+        PANEL_SIZE,
+        PANEL_OFFSET,
+        "",
+        ManaPanel,
+    );
+    commands.entity(text).insert(ManaPanelText);
+}
+
+fn show_mana(
+    local_player: Option<Res<LocalPlayerID>>,
+    manas: Query<(&Mana, &PlayerID)>,
+    registry: Res<SpellRegistry>,
+    mut text: Query<&mut Text, With<ManaPanelText>>,
+) {
+    let (Some(local_player), Ok(mut text)) = (local_player, text.get_single_mut()) else {
+        return;
+    };
+    let Some((mana, _)) = manas
+        .iter()
+        .find(|(_, id)| id.handle == local_player.handle)
+    else {
+        return;
+    };
+
+    let mut s = format!("Mana {:.0}/{MAX_MANA:.0}", mana.current);
+    for (id, &frames) in mana.cooldowns.iter().enumerate().filter(|(_, &f)| f > 0) {
+        if let Some(spell) = registry.get(id as u32) {
+            let _ = write!(s, "\n{} {:.1}s", spell.name, frames as f32 / FPS as f32);
+        }
+    }
+    if let Some(failed) = mana.failed {
+        let name = registry
+            .get(failed.spell)
+            .map_or("That spell", |s| s.name.as_str());
+        s.push('\n');
+        s.push_str(&match failed.reason {
+            CastFailure::NotEnoughMana => format!("Not enough mana for {name}"),
+            CastFailure::CoolingDown => format!("{name} isn't ready yet"),
+        });
+    }
+    text.sections[0].value = s;
+}
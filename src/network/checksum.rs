@@ -0,0 +1,226 @@
+use std::{
+    any::type_name,
+    collections::BTreeMap,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+};
+
+use bevy::prelude::*;
+use bevy_ggrs::{prelude::*, RollbackFrameCount, SaveWorld};
+
+// How many frames of per-entity state we keep around, to compare against re-simulations
+// and to dump once a peer reports a desync.
+pub(super) const HISTORY_FRAMES: i32 = 256;
+
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
+enum EntityChecksumSet {
+    Record,
+    Compare,
+}
+
+// The state of one rollback entity at the end of a frame.
+#[derive(Default, Clone, PartialEq)]
+pub(super) struct EntityState {
+    // Checksum and debug representation of each registered component, keyed by type name
+    pub(super) components: BTreeMap<&'static str, (u64, String)>,
+}
+
+// Per-entity checksums of every component registered with `checksum_rollback_component`.
+// Unlike the single checksum GGRS compares, this can tell us which entity went wrong.
+#[derive(Resource, Default)]
+pub(super) struct EntityChecksums {
+    current: BTreeMap<Entity, EntityState>,
+    history: BTreeMap<i32, BTreeMap<Entity, EntityState>>,
+}
+
+impl EntityChecksums {
+    pub(super) fn frame(&self, frame: i32) -> Option<&BTreeMap<Entity, EntityState>> {
+        self.history.get(&frame)
+    }
+}
+
+pub trait RollbackChecksumApp {
+    // Includes a component in the world checksum GGRS uses, and in our per-entity checksums.
+    fn checksum_rollback_component<T: Component + Debug>(
+        &mut self,
+        hasher: fn(&T) -> u64,
+    ) -> &mut Self;
+}
+
+impl RollbackChecksumApp for App {
+    fn checksum_rollback_component<T: Component + Debug>(
+        &mut self,
+        hasher: fn(&T) -> u64,
+    ) -> &mut Self {
+        self.checksum_component::<T>(hasher).add_systems(
+            SaveWorld,
+            (move |frame_checksums: ResMut<EntityChecksums>,
+                   components: Query<(Entity, &T), With<Rollback>>| {
+                record_component(hasher, frame_checksums, components)
+            })
+            .in_set(EntityChecksumSet::Record),
+        )
+    }
+}
+
+pub(super) struct ChecksumPlugin;
+
+impl Plugin for ChecksumPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EntityChecksums>()
+            .configure_sets(
+                SaveWorld,
+                EntityChecksumSet::Record.before(EntityChecksumSet::Compare),
+            )
+            .add_systems(
+                SaveWorld,
+                store_frame_checksums.in_set(EntityChecksumSet::Compare),
+            );
+    }
+}
+
+fn record_component<T: Component + Debug>(
+    hasher: fn(&T) -> u64,
+    mut frame_checksums: ResMut<EntityChecksums>,
+    components: Query<(Entity, &T), With<Rollback>>,
+) {
+    for (entity, component) in components.iter() {
+        frame_checksums
+            .current
+            .entry(entity)
+            .or_default()
+            .components
+            .insert(
+                type_name::<T>(),
+                (hasher(component), format!("{component:?}")),
+            );
+    }
+}
+
+// When a frame we've already seen is simulated again, the only thing that should differ is
+// anything affected by changed inputs. In a SyncTest session inputs never change, so any
+// difference means something isn't deterministic or isn't being rolled back.
+fn store_frame_checksums(
+    mut frame_checksums: ResMut<EntityChecksums>,
+    frame: Res<RollbackFrameCount>,
+    session: Option<Res<Session<crate::WizGgrsConfig>>>,
+) {
+    let frame = **frame;
+    let current = std::mem::take(&mut frame_checksums.current);
+    let is_sync_test = matches!(session.as_deref(), Some(Session::SyncTest(_)));
+
+    if let (true, Some(previous)) = (is_sync_test, frame_checksums.history.get(&frame)) {
+        report_mismatches(frame, previous, &current);
+    }
+
+    frame_checksums.history.insert(frame, current);
+    frame_checksums
+        .history
+        .retain(|f, _| *f > frame - HISTORY_FRAMES && *f <= frame);
+}
+
+fn report_mismatches(
+    frame: i32,
+    previous: &BTreeMap<Entity, EntityState>,
+    current: &BTreeMap<Entity, EntityState>,
+) {
+    for (entity, state) in current.iter() {
+        // Entities can be respawned with a new id on rollback, we can only compare ones that kept theirs
+        let Some(previous_state) = previous.get(entity) else {
+            continue;
+        };
+        for (component, (checksum, value)) in state.components.iter() {
+            match previous_state.components.get(component) {
+                Some((previous_checksum, _)) if previous_checksum == checksum => {}
+                Some((_, previous_value)) => error!(
+                    "checksum mismatch at frame {frame} on entity {entity:?}: \
+                     {component} was {previous_value} and is now {value}"
+                ),
+                None => error!(
+                    "checksum mismatch at frame {frame} on entity {entity:?}: \
+                     {component} was missing and is now {value}"
+                ),
+            }
+        }
+    }
+}
+
+// FNV-1a, for anything hashed that's compared between peers or kept in recordings.
+// DefaultHasher is free to change between Rust releases, this hashes the same on every build.
+// Integers are hashed little endian whatever the platform, and usizes as u64s.
+#[derive(Clone, Copy)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+// For anything that implements Hash. bevy_ggrs' own checksums use bevy's hasher, which isn't
+// promised to be the same between platforms, so the headset and a desktop could disagree.
+pub fn stable_hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Hashes the bit patterns of some floats, for components that don't implement Hash.
+pub fn hash_floats(values: &[f32]) -> u64 {
+    let mut hasher = StableHasher::default();
+    for v in values {
+        v.to_bits().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+pub fn hash_transform(t: &Transform) -> u64 {
+    hash_floats(&[
+        t.translation.x,
+        t.translation.y,
+        t.translation.z,
+        t.rotation.x,
+        t.rotation.y,
+        t.rotation.z,
+        t.rotation.w,
+        t.scale.x,
+        t.scale.y,
+        t.scale.z,
+    ])
+}
+
+pub fn hash_timer(timer: &Timer) -> u64 {
+    let mut hasher = StableHasher::default();
+    timer.elapsed().hash(&mut hasher);
+    timer.duration().hash(&mut hasher);
+    timer.finished().hash(&mut hasher);
+    hasher.finish()
+}
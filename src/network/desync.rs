@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use bevy_ggrs::{LocalPlayers, PlayerInputs, RollbackFrameCount};
+
+use super::{
+    checksum::{EntityChecksums, HISTORY_FRAMES},
+    ConnectionSettings, LocalPlayerID, NetworkEvent,
+};
+use crate::{PlayerInput, WizGgrsConfig};
+
+// The inputs our local players simulated each frame with, so a desync can be traced back to them.
+#[derive(Resource, Default)]
+pub(super) struct InputHistory(BTreeMap<i32, Vec<(usize, PlayerInput)>>);
+
+pub(super) fn record_local_inputs(
+    mut history: ResMut<InputHistory>,
+    frame: Res<RollbackFrameCount>,
+    local_players: Res<LocalPlayers>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+) {
+    let frame = **frame;
+    let frame_inputs = local_players
+        .0
+        .iter()
+        .map(|&handle| (handle, inputs[handle].0))
+        .collect();
+    // A rollback resimulates frames we've already recorded, the latest run is the one that counts
+    history.0.insert(frame, frame_inputs);
+    history
+        .0
+        .retain(|f, _| *f > frame - HISTORY_FRAMES && *f <= frame);
+}
+
+pub(super) fn write_desync_reports(
+    mut events: EventReader<NetworkEvent>,
+    checksums: Res<EntityChecksums>,
+    inputs: Res<InputHistory>,
+    local_player: Option<Res<LocalPlayerID>>,
+    settings: Res<ConnectionSettings>,
+) {
+    for event in events.read() {
+        // Only players compare checksums, so there's always a local player here
+        let NetworkEvent::DesyncDetected {
+            frame,
+            handle,
+            local_checksum,
+            remote_checksum,
+        } = *event
+        else {
+            continue;
+        };
+        error!(
+            "desync at frame {frame} with player {handle}: \
+             our checksum is {local_checksum:x}, theirs is {remote_checksum:x}"
+        );
+        let report = DesyncReport {
+            frame,
+            local_checksum,
+            remote_checksum,
+            peer_handle: handle,
+            local_handle: local_player.as_ref().map_or(0, |p| p.handle),
+        };
+        match report.write(&settings.desync_dump_dir, &checksums, &inputs) {
+            Ok(path) => error!("wrote desync report to {}", path.display()),
+            Err(e) => error!("failed to write desync report: {e}"),
+        }
+    }
+}
+
+struct DesyncReport {
+    frame: i32,
+    local_checksum: u128,
+    remote_checksum: u128,
+    peer_handle: usize,
+    local_handle: usize,
+}
+
+impl DesyncReport {
+    // Plain text, so reports from both peers can be compared with diff
+    fn write(
+        &self,
+        dir: &Path,
+        checksums: &EntityChecksums,
+        inputs: &InputHistory,
+    ) -> std::io::Result<PathBuf> {
+        let mut report = String::new();
+        let _ = writeln!(report, "frame: {}", self.frame);
+        let _ = writeln!(report, "player: {}", self.local_handle);
+        let _ = writeln!(report, "peer: player {}", self.peer_handle);
+        let _ = writeln!(report, "local checksum: {:x}", self.local_checksum);
+        let _ = writeln!(report, "remote checksum: {:x}", self.remote_checksum);
+
+        let _ = writeln!(report, "\nentities:");
+        match checksums.frame(self.frame) {
+            Some(entities) => {
+                for (entity, state) in entities.iter() {
+                    let _ = writeln!(report, "{entity:?}");
+                    for (component, (checksum, value)) in state.components.iter() {
+                        let _ = writeln!(report, "    {component} ({checksum:016x}): {value}");
+                    }
+                }
+            }
+            None => {
+                let _ = writeln!(report, "    frame {} is no longer recorded", self.frame);
+            }
+        }
+
+        let _ = writeln!(report, "\nlocal inputs:");
+        for (frame, frame_inputs) in inputs.0.range(..=self.frame) {
+            for (handle, input) in frame_inputs.iter() {
+                let _ = writeln!(report, "{frame} {handle}: {input:?}");
+            }
+        }
+
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "desync-frame{}-player{}.txt",
+            self.frame, self.local_handle
+        ));
+        fs::write(&path, report)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,499 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    handshake::{ClientHandshake, HostHandshake},
+    lobby::LobbyPlayer,
+    ConnectionArgs, ConnectionSettings, DEFAULT_HOST_PORT,
+};
+
+// Well known port that a host listens on for discovery probes and join requests.
+pub(super) const DISCOVERY_PORT: u16 = 7999;
+// Every discovery packet starts with this, so we can ignore unrelated broadcast traffic.
+const DISCOVERY_MAGIC: u32 = u32::from_be_bytes(*b"WIZD");
+// How often clients re-send probes and join requests while they haven't heard back.
+const PROBE_INTERVAL_SECS: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DiscoveryPacket {
+    magic: u32,
+    message: DiscoveryMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum DiscoveryMessage {
+    // Sent by a client to the broadcast address, asking any hosts to advertise themselves.
+    Probe,
+    // A host's answer to a probe.
+    Advertise {
+        session_name: String,
+        joined: usize,
+        num_players: usize,
+    },
+    // Sent by a client to the host it wants to join, with the port it will run GGRS on.
+    // Spectators don't take up a player slot, so they can join a full session.
+    JoinRequest {
+        ggrs_port: u16,
+        spectator: bool,
+    },
+    JoinAccepted {
+        handle: usize,
+    },
+    JoinRejected,
+}
+
+fn send(socket: &UdpSocket, addr: SocketAddr, message: DiscoveryMessage) {
+    let packet = DiscoveryPacket {
+        magic: DISCOVERY_MAGIC,
+        message,
+    };
+    let bytes = bincode::serialize(&packet).expect("failed to serialize discovery packet");
+    if let Err(e) = socket.send_to(&bytes, addr) {
+        debug!("failed to send discovery packet to {addr}: {e}");
+    }
+}
+
+fn receive(socket: &UdpSocket) -> Vec<(SocketAddr, DiscoveryMessage)> {
+    let mut messages = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, addr)) => match bincode::deserialize::<DiscoveryPacket>(&buf[..len]) {
+                Ok(packet) if packet.magic == DISCOVERY_MAGIC => {
+                    messages.push((addr, packet.message))
+                }
+                _ => debug!("ignoring malformed discovery packet from {addr}"),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return messages,
+            // On some platforms a previous send to an unreachable port shows up here, just skip it
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                warn!("discovery socket error: {e}");
+                return messages;
+            }
+        }
+    }
+}
+
+struct JoinedPeer {
+    discovery_addr: SocketAddr,
+    ggrs_addr: SocketAddr,
+    ready: bool,
+}
+
+#[derive(Resource)]
+pub(super) struct HostDiscovery {
+    socket: UdpSocket,
+    session_name: String,
+    num_players: usize,
+    // Each client that has joined, indexed by handle - 1.
+    // Players that leave or are kicked leave a gap, which the next one to join fills.
+    clients: Vec<Option<JoinedPeer>>,
+    // Each spectator, indexed by handle - num_players
+    spectators: Vec<Option<JoinedPeer>>,
+}
+
+impl HostDiscovery {
+    pub(super) fn new(session_name: String, num_players: usize) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            session_name,
+            num_players,
+            clients: Vec::new(),
+            spectators: Vec::new(),
+        })
+    }
+
+    fn joined(&self) -> usize {
+        self.clients.iter().flatten().count()
+    }
+
+    fn is_full(&self) -> bool {
+        self.joined() + 1 >= self.num_players
+    }
+
+    // Spectator handles come after every player's, as GGRS expects
+    fn handle_of(&self, addr: SocketAddr) -> Option<usize> {
+        let position = |peers: &[Option<JoinedPeer>]| {
+            peers
+                .iter()
+                .position(|p| matches!(p, Some(p) if p.discovery_addr == addr))
+        };
+        match position(&self.clients) {
+            Some(i) => Some(i + 1),
+            None => position(&self.spectators).map(|i| self.num_players + i),
+        }
+    }
+
+    fn join(&mut self, addr: SocketAddr, ggrs_port: u16, spectator: bool) -> usize {
+        let peer = Some(JoinedPeer {
+            discovery_addr: addr,
+            ggrs_addr: SocketAddr::new(addr.ip(), ggrs_port),
+            ready: false,
+        });
+        let peers = if spectator {
+            &mut self.spectators
+        } else {
+            &mut self.clients
+        };
+        let i = match peers.iter().position(Option::is_none) {
+            Some(i) => i,
+            None => {
+                peers.push(None);
+                peers.len() - 1
+            }
+        };
+        peers[i] = peer;
+        if spectator {
+            self.num_players + i
+        } else {
+            i + 1
+        }
+    }
+
+    fn peer(&self, handle: usize) -> Option<&JoinedPeer> {
+        match handle.checked_sub(self.num_players) {
+            Some(i) => self.spectators.get(i)?.as_ref(),
+            None => self.clients.get(handle.checked_sub(1)?)?.as_ref(),
+        }
+    }
+
+    fn peer_mut(&mut self, handle: usize) -> Option<&mut Option<JoinedPeer>> {
+        match handle.checked_sub(self.num_players) {
+            Some(i) => self.spectators.get_mut(i),
+            None => self.clients.get_mut(handle.checked_sub(1)?),
+        }
+    }
+
+    pub(super) fn set_ready(&mut self, handle: usize, ready: bool) {
+        if let Some(Some(peer)) = self.peer_mut(handle) {
+            peer.ready = ready;
+        }
+    }
+
+    // Frees up the handle, returning whether anyone had it
+    pub(super) fn remove(&mut self, handle: usize) -> bool {
+        match self.peer_mut(handle) {
+            Some(peer) => peer.take().is_some(),
+            None => false,
+        }
+    }
+
+    pub(super) fn spectator_handles(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.spectators.len())
+            .filter(|&i| self.spectators[i].is_some())
+            .map(|i| self.num_players + i)
+    }
+
+    // Every client that's joined as a player, the host isn't included
+    pub(super) fn roster(&self) -> impl Iterator<Item = LobbyPlayer> + '_ {
+        self.clients.iter().enumerate().filter_map(|(i, peer)| {
+            peer.as_ref().map(|peer| LobbyPlayer {
+                handle: i + 1,
+                ready: peer.ready,
+            })
+        })
+    }
+
+    // Closes up the gaps left by anyone who went, so GGRS gets players 0..n and the spectators straight after,
+    // and tells the handshake about everyone's new handle. Spectators that never connected are left out.
+    pub(super) fn start(
+        &self,
+        handshake: &mut HostHandshake,
+        settings: &ConnectionSettings,
+    ) -> ConnectionArgs {
+        let players: Vec<usize> = self.roster().map(|p| p.handle).collect();
+        let spectators: Vec<usize> = self
+            .spectator_handles()
+            .filter(|&h| handshake.is_connected(h))
+            .collect();
+        let ggrs_addr = |handle| {
+            self.peer(handle)
+                .expect("handles come from the peers that joined")
+                .ggrs_addr
+        };
+
+        let local_port = settings.local_port.unwrap_or(DEFAULT_HOST_PORT);
+        let mut player_addrs = vec![SocketAddr::from((Ipv4Addr::LOCALHOST, local_port))];
+        player_addrs.extend(players.iter().map(|&h| ggrs_addr(h)));
+        let mut args = ConnectionArgs::from_settings(settings, local_port, 0, player_addrs);
+        args.spectators = spectators.iter().map(|&h| ggrs_addr(h)).collect();
+
+        handshake.renumber(|old| match players.iter().position(|&h| h == old) {
+            Some(i) => Some(i + 1),
+            None => spectators
+                .iter()
+                .position(|&h| h == old)
+                .map(|i| players.len() + 1 + i),
+        });
+        args
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct ClientDiscovery {
+    socket: UdpSocket,
+    probe_targets: Vec<SocketAddr>,
+    probe_timer: Timer,
+    // Discovery address of the host we're joining
+    host: Option<SocketAddr>,
+    handle: Option<usize>,
+    spectator: bool,
+    // Hosts that kicked us, which we won't try joining again
+    refused: Vec<SocketAddr>,
+}
+
+impl ClientDiscovery {
+    // The client's GGRS socket later reuses this port, 0 lets the OS pick a free one
+    pub(super) fn new(port: u16, spectator: bool) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+        Ok(Self {
+            socket,
+            probe_targets: vec![
+                SocketAddrV4::new(Ipv4Addr::BROADCAST, DISCOVERY_PORT).into(),
+                // Lets several instances find each other on one machine
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, DISCOVERY_PORT).into(),
+            ],
+            probe_timer: Timer::from_seconds(PROBE_INTERVAL_SECS, TimerMode::Repeating),
+            host: None,
+            handle: None,
+            spectator,
+            refused: Vec::new(),
+        })
+    }
+
+    fn local_port(&self) -> u16 {
+        self.socket
+            .local_addr()
+            .expect("discovery socket has no local address")
+            .port()
+    }
+
+    fn join_request(&self) -> DiscoveryMessage {
+        DiscoveryMessage::JoinRequest {
+            ggrs_port: self.local_port(),
+            spectator: self.spectator,
+        }
+    }
+
+    // Stops trying to join the host we're waiting on, and goes looking for another session
+    pub(super) fn refuse_host(&mut self) {
+        if let Some(host) = self.host.take() {
+            self.refused.push(host);
+        }
+        self.handle = None;
+    }
+}
+
+// Lets players that dropped out of a running match take their handle back.
+#[derive(Resource)]
+pub(super) struct RejoinDiscovery {
+    socket: UdpSocket,
+    session_name: String,
+    num_players: usize,
+    // Each vacant handle, with the discovery and GGRS address of whoever has claimed it
+    vacant: BTreeMap<usize, Option<(SocketAddr, SocketAddr)>>,
+}
+
+impl RejoinDiscovery {
+    pub(super) fn new(session_name: String, num_players: usize) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            session_name,
+            num_players,
+            vacant: BTreeMap::new(),
+        })
+    }
+
+    pub(super) fn vacate(&mut self, handle: usize) {
+        self.vacant.insert(handle, None);
+    }
+
+    // The GGRS address of every rejoined player, once every vacant handle has been claimed
+    // and has an open handshake connection.
+    pub(super) fn rejoined(&self, handshake: &HostHandshake) -> Option<Vec<(usize, SocketAddr)>> {
+        self.vacant
+            .iter()
+            .map(|(&handle, claim)| match claim {
+                Some((_, ggrs_addr)) if handshake.is_connected(handle) => {
+                    Some((handle, *ggrs_addr))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn handle_of(&self, addr: SocketAddr) -> Option<usize> {
+        self.vacant
+            .iter()
+            .find(|(_, claim)| matches!(claim, Some((a, _)) if *a == addr))
+            .map(|(&handle, _)| handle)
+    }
+}
+
+// Answers clients looking for a session and lets them join. The lobby decides when the match starts.
+pub(super) fn host_wait(mut discovery: ResMut<HostDiscovery>) {
+    for (addr, message) in receive(&discovery.socket) {
+        match message {
+            DiscoveryMessage::Probe => {
+                let advert = DiscoveryMessage::Advertise {
+                    session_name: discovery.session_name.clone(),
+                    joined: discovery.joined() + 1,
+                    num_players: discovery.num_players,
+                };
+                send(&discovery.socket, addr, advert);
+            }
+            DiscoveryMessage::JoinRequest {
+                ggrs_port,
+                spectator,
+            } => {
+                // Join requests are re-sent until answered, so a client may already be known
+                let handle = match discovery.handle_of(addr) {
+                    Some(handle) => handle,
+                    None if spectator || !discovery.is_full() => {
+                        let handle = discovery.join(addr, ggrs_port, spectator);
+                        if spectator {
+                            info!("{addr} is spectating the session");
+                        } else {
+                            info!("{addr} joined the session as player {handle}");
+                        }
+                        handle
+                    }
+                    None => {
+                        send(&discovery.socket, addr, DiscoveryMessage::JoinRejected);
+                        continue;
+                    }
+                };
+                send(
+                    &discovery.socket,
+                    addr,
+                    DiscoveryMessage::JoinAccepted { handle },
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(super) fn client_wait(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut discovery: ResMut<ClientDiscovery>,
+    handshake: Option<Res<ClientHandshake>>,
+) {
+    for (addr, message) in receive(&discovery.socket) {
+        match message {
+            DiscoveryMessage::Advertise {
+                session_name,
+                joined,
+                num_players,
+            } if discovery.host.is_none()
+                && !discovery.refused.contains(&addr)
+                && (discovery.spectator || joined < num_players) =>
+            {
+                info!(
+                    "found session \"{session_name}\" at {addr} ({joined}/{num_players} players)"
+                );
+                discovery.host = Some(addr);
+                // Don't wait for the timer before asking to join
+                send(&discovery.socket, addr, discovery.join_request());
+            }
+            DiscoveryMessage::JoinAccepted { handle }
+                if Some(addr) == discovery.host && discovery.handle.is_none() =>
+            {
+                // Open the reliable channel the host will send the session info over
+                match ClientHandshake::connect(addr.ip(), handle, discovery.local_port()) {
+                    Ok(handshake) => {
+                        if discovery.spectator {
+                            info!("joined session as spectator {handle}");
+                        } else {
+                            info!("joined session as player {handle}");
+                        }
+                        commands.insert_resource(handshake);
+                        discovery.handle = Some(handle);
+                    }
+                    Err(e) => {
+                        warn!("failed to open handshake connection to {addr}: {e}");
+                        discovery.host = None;
+                    }
+                }
+            }
+            DiscoveryMessage::JoinRejected if Some(addr) == discovery.host => {
+                warn!("{addr} rejected our join request, looking for another session");
+                discovery.host = None;
+            }
+            _ => {}
+        }
+    }
+
+    if !discovery.probe_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    match (discovery.host, discovery.handle) {
+        (None, _) => {
+            for target in discovery.probe_targets.iter() {
+                send(&discovery.socket, *target, DiscoveryMessage::Probe);
+            }
+        }
+        (Some(host), None) => send(&discovery.socket, host, discovery.join_request()),
+        // Joined, waiting for the host to start the session.
+        // If the handshake connection was dropped, start looking for a session again.
+        (Some(_), Some(_)) => {
+            if handshake.is_none() {
+                discovery.host = None;
+                discovery.handle = None;
+            }
+        }
+    }
+}
+
+// Answers clients looking for a session while the match is running, the same way `host_wait` does.
+// Only vacant player handles are handed out, spectators can only join before the match starts.
+pub(super) fn host_answer_rejoins(mut discovery: ResMut<RejoinDiscovery>) {
+    for (addr, message) in receive(&discovery.socket) {
+        match message {
+            DiscoveryMessage::Probe => {
+                let unclaimed = discovery.vacant.values().filter(|c| c.is_none()).count();
+                let advert = DiscoveryMessage::Advertise {
+                    session_name: discovery.session_name.clone(),
+                    joined: discovery.num_players - unclaimed,
+                    num_players: discovery.num_players,
+                };
+                send(&discovery.socket, addr, advert);
+            }
+            DiscoveryMessage::JoinRequest {
+                ggrs_port,
+                spectator: false,
+            } => {
+                let claimed = discovery.handle_of(addr).or_else(|| {
+                    let (&handle, claim) =
+                        discovery.vacant.iter_mut().find(|(_, c)| c.is_none())?;
+                    *claim = Some((addr, SocketAddr::new(addr.ip(), ggrs_port)));
+                    info!("{addr} is rejoining as player {handle}");
+                    Some(handle)
+                });
+                let reply = match claimed {
+                    Some(handle) => DiscoveryMessage::JoinAccepted { handle },
+                    None => DiscoveryMessage::JoinRejected,
+                };
+                send(&discovery.socket, addr, reply);
+            }
+            DiscoveryMessage::JoinRequest {
+                spectator: true, ..
+            } => send(&discovery.socket, addr, DiscoveryMessage::JoinRejected),
+            _ => {}
+        }
+    }
+}
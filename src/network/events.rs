@@ -0,0 +1,132 @@
+use std::{net::SocketAddr, time::Duration};
+
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs::GgrsEvent, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use super::ConnectionArgs;
+use crate::{boss::BossPhase, duel::DuelScore, WizGgrsConfig};
+
+// What GGRS tells us about our peers, with addresses turned back into handles,
+// and the one thing that stops us talking to them at all.
+#[derive(Event, Debug, Clone)]
+pub enum NetworkEvent {
+    Synchronized {
+        handle: usize,
+    },
+    // We haven't heard from a peer in a while, they get dropped if it lasts `disconnect_timeout`.
+    Interrupted {
+        handle: usize,
+        disconnect_timeout: Duration,
+    },
+    Resumed {
+        handle: usize,
+    },
+    Disconnected {
+        handle: usize,
+    },
+    DesyncDetected {
+        frame: i32,
+        handle: usize,
+        local_checksum: u128,
+        remote_checksum: u128,
+    },
+    // Couldn't bind a socket we need to find or talk to peers, so there's no session
+    SocketFailed {
+        port: u16,
+        error: String,
+    },
+}
+
+// Sent on every peer when the host restarts the session to let dropped players back in.
+// The network plugin despawns every rollback entity, anything else that needs resetting listens for this.
+// It goes out to the clients with the new session's info, so only one of the fields means anything for a given mode.
+#[derive(Event, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SessionRestarted {
+    // Phase the host's boss was in, so the match carries on from there
+    pub boss_phase: BossPhase,
+    // Likewise the score of a duel
+    pub duel_score: DuelScore,
+}
+
+impl ConnectionArgs {
+    // Spectators only ever talk to the host, whose handle is 0
+    pub(super) fn handle_of(&self, addr: SocketAddr) -> Option<usize> {
+        self.players
+            .iter()
+            .enumerate()
+            .position(|(h, p)| h != self.local_handle && *p == addr)
+            .or_else(|| {
+                self.spectators
+                    .iter()
+                    .position(|s| *s == addr)
+                    .map(|i| self.players.len() + i)
+            })
+    }
+}
+
+pub(super) fn read_ggrs_events(
+    mut session: ResMut<Session<WizGgrsConfig>>,
+    args: Res<ConnectionArgs>,
+    mut network_events: EventWriter<NetworkEvent>,
+) {
+    let events: Vec<_> = match &mut *session {
+        Session::P2P(session) => session.events().collect(),
+        Session::Spectator(session) => session.events().collect(),
+        Session::SyncTest(_) => return,
+    };
+    let is_spectator = matches!(&*session, Session::Spectator(_));
+    let handle_of = |addr| {
+        if is_spectator {
+            Some(0)
+        } else {
+            args.handle_of(addr)
+        }
+    };
+
+    for event in events {
+        match NetworkEvent::from_ggrs(&event, handle_of) {
+            Some(event) => network_events.send(event),
+            None => debug!("ignoring GGRS event {event:?}"),
+        };
+    }
+}
+
+impl NetworkEvent {
+    // None for events we don't pass on, or ones from a peer we don't know about
+    fn from_ggrs(
+        event: &GgrsEvent<WizGgrsConfig>,
+        handle_of: impl Fn(SocketAddr) -> Option<usize>,
+    ) -> Option<Self> {
+        Some(match *event {
+            GgrsEvent::Synchronized { addr } => Self::Synchronized {
+                handle: handle_of(addr)?,
+            },
+            GgrsEvent::NetworkInterrupted {
+                addr,
+                disconnect_timeout,
+            } => Self::Interrupted {
+                handle: handle_of(addr)?,
+                disconnect_timeout: Duration::from_millis(disconnect_timeout as u64),
+            },
+            GgrsEvent::NetworkResumed { addr } => Self::Resumed {
+                handle: handle_of(addr)?,
+            },
+            GgrsEvent::Disconnected { addr } => Self::Disconnected {
+                handle: handle_of(addr)?,
+            },
+            GgrsEvent::DesyncDetected {
+                frame,
+                local_checksum,
+                remote_checksum,
+                addr,
+            } => Self::DesyncDetected {
+                frame,
+                handle: handle_of(addr)?,
+                local_checksum,
+                remote_checksum,
+            },
+            _ => return None,
+        })
+    }
+}
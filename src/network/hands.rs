@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use bevy_ggrs::{AddRollbackCommandExtension, PlayerInputs};
+use bevy_oxr::xr_input::hands::common::HandResource;
+
+use super::{ConnectionArgs, LocalPlayerID, PlayerID, SharedAnchor};
+use crate::{
+    assets::{AssetHandles, MatName, MeshName},
+    input::HAND_JOINTS,
+    WizGgrsConfig,
+};
+
+// The asset sphere is 10cm across, finger joints are more like 1cm.
+const JOINT_SCALE: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HandSide {
+    Left,
+    Right,
+}
+
+// One finger joint of a player's hand skeleton, `joint` indexes into `finger_joints`.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlayerFingerJoint {
+    pub side: HandSide,
+    pub joint: usize,
+}
+
+// Every finger joint a hand skeleton input carries, in the order they're sent.
+// The metacarpals barely move relative to the palm, so they're left out.
+pub(super) fn finger_joints(hand: &HandResource) -> [Entity; HAND_JOINTS] {
+    [
+        hand.thumb.proximal,
+        hand.thumb.distal,
+        hand.thumb.tip,
+        hand.index.proximal,
+        hand.index.intermediate,
+        hand.index.distal,
+        hand.index.tip,
+        hand.middle.proximal,
+        hand.middle.intermediate,
+        hand.middle.distal,
+        hand.middle.tip,
+        hand.ring.proximal,
+        hand.ring.intermediate,
+        hand.ring.distal,
+        hand.ring.tip,
+        hand.little.proximal,
+        hand.little.intermediate,
+        hand.little.distal,
+        hand.little.tip,
+    ]
+}
+
+// Every peer spawns the joints of every player, whether or not they're sending them,
+// so everyone has the same rollback entities. Only other players' joints are drawn.
+pub(super) fn spawn_finger_joints(
+    mut commands: Commands,
+    args: Res<ConnectionArgs>,
+    local_player: Option<Res<LocalPlayerID>>,
+    asset_handles: Res<AssetHandles>,
+) {
+    for handle in 0..args.players.len() {
+        let is_local = local_player.as_ref().is_some_and(|p| p.handle == handle);
+        for side in [HandSide::Left, HandSide::Right] {
+            for joint in 0..HAND_JOINTS {
+                let mut e = commands.spawn((
+                    SpatialBundle {
+                        transform: Transform::from_scale(Vec3::splat(JOINT_SCALE)),
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    PlayerID { handle },
+                    PlayerFingerJoint { side, joint },
+                ));
+                if !is_local {
+                    e.insert((
+                        asset_handles.meshes[MeshName::Sphere as usize].clone(),
+                        asset_handles.mats[MatName::Green as usize].clone(),
+                    ));
+                }
+                e.add_rollback();
+            }
+        }
+    }
+}
+
+// Joints of players that aren't sending them are hidden away in their palms.
+pub(super) fn move_finger_joints(
+    mut joints: Query<(
+        &mut Transform,
+        &mut Visibility,
+        &PlayerID,
+        &PlayerFingerJoint,
+    )>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    anchor: Res<SharedAnchor>,
+) {
+    for (mut t, mut visibility, p, finger) in joints.iter_mut() {
+        let input = inputs[p.handle].0;
+        let (palm, joints) = match finger.side {
+            HandSide::Left => (input.left_hand_pos(), input.left_hand_joints()),
+            HandSide::Right => (input.right_hand_pos(), input.right_hand_joints()),
+        };
+        match joints {
+            Some(joints) => {
+                t.translation = anchor.0.transform_point(joints[finger.joint]);
+                *visibility = Visibility::Inherited;
+            }
+            None => {
+                t.translation = anchor.0.transform_point(palm);
+                *visibility = Visibility::Hidden;
+            }
+        }
+    }
+}
@@ -0,0 +1,550 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    discovery::ClientDiscovery,
+    lobby::{Lobby, LobbyPlayer},
+    settings::Role,
+    ConnectionArgs, ConnectionSettings, GameMode, NetworkingState, SessionRestarted, SessionSeed,
+    SharedAnchor,
+};
+use crate::{spell_registry::SpellRegistry, FPS};
+
+// Bump this whenever `HandshakeMessage` or `SessionInfo` change shape.
+const HANDSHAKE_VERSION: u32 = 7;
+// The host listens for handshake connections on the same port number it uses for discovery.
+pub(super) const HANDSHAKE_PORT: u16 = 7999;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// Sends block the frame, so a peer that's stopped reading gets hung up on rather than waited for.
+const SEND_TIMEOUT: Duration = Duration::from_millis(200);
+// Refuse frames bigger than this, a peer list + anchor is nowhere near it.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HandshakeFrame {
+    version: u32,
+    message: HandshakeMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum HandshakeMessage {
+    // First thing a client sends after connecting, so the host knows which player the connection belongs to.
+    ClientHello { handle: usize },
+    // A client readying up in the lobby, or changing its mind.
+    Ready { ready: bool },
+    // Who's in the lobby, sent to every client whenever it changes.
+    Lobby(LobbyInfo),
+    // The host kicked the client out of the lobby.
+    Kicked,
+    SessionInfo(SessionInfo),
+    Rejected { reason: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LobbyInfo {
+    // The handle the receiving client has in the lobby. It can change as others leave.
+    handle: usize,
+    players: Vec<LobbyPlayer>,
+    spectators: usize,
+    // Told to clients straight away, so one that can't play by them can leave before the match starts
+    rules: MatchRules,
+}
+
+// What every peer has to agree on to play together, on top of the handshake version and fps.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct MatchRules {
+    mode: GameMode,
+    // Checksum of the spell registry
+    spells: u64,
+}
+
+impl MatchRules {
+    pub(super) fn new(settings: &ConnectionSettings, registry: &SpellRegistry) -> Self {
+        Self {
+            mode: settings.mode,
+            spells: registry.checksum(),
+        }
+    }
+
+    // Why we can't play by the host's rules, if we can't.
+    fn mismatch(&self, ours: &Self) -> Option<String> {
+        if self.mode != ours.mode {
+            Some(format!(
+                "host is playing {:?} mode but we're set up for {:?}",
+                self.mode, ours.mode
+            ))
+        } else if self.spells != ours.spells {
+            Some("host has different spells to us".to_owned())
+        } else {
+            None
+        }
+    }
+}
+
+// What a client asked of the host while in the lobby.
+pub(super) enum LobbyRequest {
+    Ready(bool),
+    // The connection closed, so the client's gone
+    Left,
+}
+
+// Everything a client needs to know to start its GGRS session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SessionInfo {
+    // The handle the receiving client plays as. Spectators get one past the last player.
+    handle: usize,
+    // GGRS address of every player, indexed by handle.
+    players: Vec<SocketAddr>,
+    fps: usize,
+    rules: MatchRules,
+    input_delay: usize,
+    max_prediction_window: usize,
+    desync_interval: u32,
+    seed: u64,
+    anchor: AnchorTransform,
+    // Set when a running match restarts to let dropped players back in, the match picks up from this
+    restart: Option<SessionRestarted>,
+}
+
+// glam's serde support isn't enabled, so the anchor is sent as plain arrays.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct AnchorTransform {
+    translation: [f32; 3],
+    rotation: [f32; 4],
+}
+
+impl From<Transform> for AnchorTransform {
+    fn from(t: Transform) -> Self {
+        Self {
+            translation: t.translation.to_array(),
+            rotation: t.rotation.to_array(),
+        }
+    }
+}
+
+impl From<AnchorTransform> for Transform {
+    fn from(t: AnchorTransform) -> Self {
+        Transform::from_translation(Vec3::from_array(t.translation))
+            .with_rotation(Quat::from_array(t.rotation))
+    }
+}
+
+// Length prefixed frames over a TCP stream. Reads are non-blocking, writes block for up to `SEND_TIMEOUT`.
+struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    // Set once the peer's closed their end, though there may still be frames left in `buf`
+    closed: bool,
+    // Set once we've hung up on them, which mustn't look like them closing
+    hung_up: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+        Ok(Self {
+            stream,
+            buf: Vec::new(),
+            closed: false,
+            hung_up: false,
+        })
+    }
+
+    fn send(&mut self, message: HandshakeMessage) -> io::Result<()> {
+        let frame = HandshakeFrame {
+            version: HANDSHAKE_VERSION,
+            message,
+        };
+        let bytes = bincode::serialize(&frame).map_err(io::Error::other)?;
+        self.stream.set_nonblocking(false)?;
+        let sent = self
+            .stream
+            .write_all(&(bytes.len() as u32).to_be_bytes())
+            .and_then(|_| self.stream.write_all(&bytes));
+        self.stream.set_nonblocking(true)?;
+        if sent.is_err() {
+            // Some of the frame may have gone, so nothing after it could be read. Hanging up
+            // means the next poll fails, and whoever polls drops the connection like any other.
+            let _ = self.stream.shutdown(Shutdown::Both);
+            self.hung_up = true;
+        }
+        sent
+    }
+
+    // Returns the next complete message, if one has arrived.
+    // Only fails with `UnexpectedEof` once every message sent before the peer closed has been returned.
+    fn poll(&mut self) -> io::Result<Option<HandshakeMessage>> {
+        if self.hung_up {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "hung up after they stopped taking what we sent",
+            ));
+        }
+        let mut chunk = [0u8; 1024];
+        while !self.closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self.next_frame()? {
+            Some(message) => Ok(Some(message)),
+            None if self.closed => Err(ErrorKind::UnexpectedEof.into()),
+            None => Ok(None),
+        }
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<HandshakeMessage>> {
+        let Some(len_bytes) = self.buf.get(..4) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("handshake frame of {len} bytes is too large"),
+            ));
+        }
+        if self.buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let payload: Vec<u8> = self.buf.drain(..4 + len).skip(4).collect();
+
+        // The version always comes first, so check it before trying to decode the rest
+        let version = payload
+            .get(..4)
+            .map(|v| u32::from_le_bytes(v.try_into().unwrap()))
+            .unwrap_or_default();
+        if version != HANDSHAKE_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("peer uses handshake version {version}, we use {HANDSHAKE_VERSION}"),
+            ));
+        }
+        let frame: HandshakeFrame = bincode::deserialize(&payload)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        Ok(Some(frame.message))
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct HostHandshake {
+    listener: TcpListener,
+    // Connections that haven't told us who they are yet
+    pending: Vec<Connection>,
+    clients: HashMap<usize, Connection>,
+}
+
+impl HostHandshake {
+    pub(super) fn new() -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, HANDSHAKE_PORT))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            pending: Vec::new(),
+            clients: HashMap::new(),
+        })
+    }
+
+    pub(super) fn is_connected(&self, handle: usize) -> bool {
+        self.clients.contains_key(&handle)
+    }
+
+    // Forgets a client's connection, so whoever takes over its handle can say hello again
+    pub(super) fn disconnect(&mut self, handle: usize) {
+        self.clients.remove(&handle);
+    }
+
+    // Moves every client to the handle `renumber` gives it, dropping the ones it gives None.
+    pub(super) fn renumber(&mut self, renumber: impl Fn(usize) -> Option<usize>) {
+        self.clients = self
+            .clients
+            .drain()
+            .filter_map(|(handle, conn)| Some((renumber(handle)?, conn)))
+            .collect();
+    }
+
+    // Every request the clients have made since we last asked.
+    pub(super) fn poll_lobby(&mut self) -> Vec<(usize, LobbyRequest)> {
+        let mut requests = Vec::new();
+        for (&handle, conn) in self.clients.iter_mut() {
+            loop {
+                match conn.poll() {
+                    Ok(Some(HandshakeMessage::Ready { ready })) => {
+                        requests.push((handle, LobbyRequest::Ready(ready)))
+                    }
+                    Ok(Some(message)) => warn!("unexpected handshake message {message:?}"),
+                    Ok(None) => break,
+                    Err(e) => {
+                        info!("player {handle} left the lobby: {e}");
+                        requests.push((handle, LobbyRequest::Left));
+                        break;
+                    }
+                }
+            }
+        }
+        for (handle, request) in requests.iter() {
+            if matches!(request, LobbyRequest::Left) {
+                self.clients.remove(handle);
+            }
+        }
+        requests
+    }
+
+    pub(super) fn send_lobby(
+        &mut self,
+        players: &[LobbyPlayer],
+        spectators: usize,
+        rules: MatchRules,
+    ) {
+        for (&handle, conn) in self.clients.iter_mut() {
+            let info = LobbyInfo {
+                handle,
+                players: players.to_vec(),
+                spectators,
+                rules,
+            };
+            if let Err(e) = conn.send(HandshakeMessage::Lobby(info)) {
+                warn!("failed to send the lobby to player {handle}: {e}");
+            }
+        }
+    }
+
+    pub(super) fn kick(&mut self, handle: usize) {
+        if let Some(mut conn) = self.clients.remove(&handle) {
+            let _ = conn.send(HandshakeMessage::Kicked);
+            // Closing just our side lets the message arrive before the client sees the connection close
+            let _ = conn.stream.shutdown(Shutdown::Write);
+        }
+    }
+}
+
+pub(super) fn host_accept_clients(mut handshake: ResMut<HostHandshake>) {
+    loop {
+        match handshake.listener.accept() {
+            Ok((stream, addr)) => match Connection::new(stream) {
+                Ok(conn) => handshake.pending.push(conn),
+                Err(e) => warn!("failed to set up handshake connection from {addr}: {e}"),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("failed to accept handshake connection: {e}");
+                break;
+            }
+        }
+    }
+
+    let handshake = &mut *handshake;
+    let mut still_pending = Vec::new();
+    for mut conn in handshake.pending.drain(..) {
+        match conn.poll() {
+            Ok(Some(HandshakeMessage::ClientHello { handle })) => {
+                info!("player {handle} connected for handshake");
+                handshake.clients.insert(handle, conn);
+            }
+            Ok(Some(message)) => warn!("unexpected handshake message {message:?}"),
+            Ok(None) => still_pending.push(conn),
+            Err(e) => {
+                warn!("dropping handshake connection: {e}");
+                let reason = e.to_string();
+                let _ = conn.send(HandshakeMessage::Rejected { reason });
+            }
+        }
+    }
+    handshake.pending = still_pending;
+}
+
+// Send every client the IP + port of every peer, the session settings,
+// and the anchor point that all clients coordinate themselves around.
+pub(super) fn host_inform_clients(
+    mut handshake: ResMut<HostHandshake>,
+    args: Res<ConnectionArgs>,
+    anchor: Res<SharedAnchor>,
+    seed: Res<SessionSeed>,
+    settings: Res<ConnectionSettings>,
+    registry: Res<SpellRegistry>,
+) {
+    // The connections stay open for the rest of the match, in case we need to restart the session
+    let rules = MatchRules::new(&settings, &registry);
+    send_session_info(&mut handshake, &args, *anchor, *seed, rules, None);
+}
+
+pub(super) fn send_session_info(
+    handshake: &mut HostHandshake,
+    args: &ConnectionArgs,
+    anchor: SharedAnchor,
+    seed: SessionSeed,
+    rules: MatchRules,
+    restart: Option<SessionRestarted>,
+) {
+    for (&handle, conn) in handshake.clients.iter_mut() {
+        // The address this client reached us on is the one it should use for our GGRS socket
+        let host_ip = conn
+            .stream
+            .local_addr()
+            .map(|a| a.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let mut players = args.players.clone();
+        players[args.local_handle] = SocketAddr::new(host_ip, args.local_port);
+
+        let info = SessionInfo {
+            handle,
+            players,
+            fps: FPS,
+            rules,
+            input_delay: args.input_delay,
+            max_prediction_window: args.max_prediction_window,
+            desync_interval: args.desync_interval,
+            seed: seed.0,
+            anchor: anchor.0.into(),
+            restart,
+        };
+        if let Err(e) = conn.send(HandshakeMessage::SessionInfo(info)) {
+            error!("failed to send session info to player {handle}: {e}");
+        }
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct ClientHandshake {
+    conn: Connection,
+    // Port our GGRS socket will bind to
+    ggrs_port: u16,
+}
+
+impl ClientHandshake {
+    pub(super) fn connect(host: IpAddr, handle: usize, ggrs_port: u16) -> io::Result<Self> {
+        let addr = SocketAddr::new(host, HANDSHAKE_PORT);
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        let mut conn = Connection::new(stream)?;
+        conn.send(HandshakeMessage::ClientHello { handle })?;
+        Ok(Self { conn, ggrs_port })
+    }
+
+    pub(super) fn send_ready(&mut self, ready: bool) {
+        if let Err(e) = self.conn.send(HandshakeMessage::Ready { ready }) {
+            warn!("failed to tell the host we're ready: {e}");
+        }
+    }
+}
+
+pub(super) fn client_await_session(
+    mut commands: Commands,
+    mut handshake: ResMut<ClientHandshake>,
+    settings: Res<ConnectionSettings>,
+    registry: Res<SpellRegistry>,
+    mut lobby: ResMut<Lobby>,
+    discovery: Option<ResMut<ClientDiscovery>>,
+    mut state: ResMut<NextState<NetworkingState>>,
+    mut restarts: EventWriter<SessionRestarted>,
+) {
+    let rules = MatchRules::new(&settings, &registry);
+    let info = match handshake.conn.poll() {
+        Ok(Some(HandshakeMessage::SessionInfo(info))) => info,
+        Ok(Some(HandshakeMessage::Lobby(info))) => {
+            if let Some(reason) = info.rules.mismatch(&rules) {
+                error!("{reason}, can't join");
+                commands.remove_resource::<ClientHandshake>();
+                if let Some(mut discovery) = discovery {
+                    discovery.refuse_host();
+                }
+                return;
+            }
+            lobby.local_handle = Some(info.handle);
+            lobby.players = info.players;
+            lobby.spectators = info.spectators;
+            return;
+        }
+        Ok(Some(HandshakeMessage::Kicked)) => {
+            error!("the host kicked us out of the lobby");
+            commands.remove_resource::<ClientHandshake>();
+            // Don't walk straight back into the session we were kicked from
+            if let Some(mut discovery) = discovery {
+                discovery.refuse_host();
+            }
+            return;
+        }
+        Ok(Some(HandshakeMessage::Rejected { reason })) => {
+            error!("host rejected handshake: {reason}");
+            commands.remove_resource::<ClientHandshake>();
+            return;
+        }
+        Ok(Some(message)) => {
+            warn!("unexpected handshake message {message:?}");
+            return;
+        }
+        Ok(None) => return,
+        // Kicking is the only reason a host hangs up on its lobby, even if the message didn't make it
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof && lobby.local_handle.is_some() => {
+            error!("the host closed the connection while we were in its lobby");
+            commands.remove_resource::<ClientHandshake>();
+            if let Some(mut discovery) = discovery {
+                discovery.refuse_host();
+            }
+            return;
+        }
+        Err(e) => {
+            error!("handshake with host failed: {e}");
+            commands.remove_resource::<ClientHandshake>();
+            return;
+        }
+    };
+
+    if info.fps != FPS {
+        error!(
+            "host runs the rollback schedule at {} fps but we run at {FPS}, can't join",
+            info.fps
+        );
+        commands.remove_resource::<ClientHandshake>();
+        return;
+    }
+    if let Some(reason) = info.rules.mismatch(&rules) {
+        error!("{reason}, can't join");
+        commands.remove_resource::<ClientHandshake>();
+        return;
+    }
+    let spectator_handle = info.handle >= info.players.len();
+    if spectator_handle != (settings.role == Role::Spectator) {
+        error!(
+            "host assigned us handle {} out of {} players, which doesn't match our role {:?}",
+            info.handle,
+            info.players.len(),
+            settings.role
+        );
+        commands.remove_resource::<ClientHandshake>();
+        return;
+    }
+
+    // Session wide settings come from the host, so every peer runs with the same ones
+    commands.insert_resource(ConnectionArgs {
+        local_port: handshake.ggrs_port,
+        local_handle: info.handle,
+        players: info.players,
+        input_delay: info.input_delay,
+        max_prediction_window: info.max_prediction_window,
+        desync_interval: info.desync_interval,
+        spectators: Vec::new(),
+    });
+    commands.insert_resource(SharedAnchor(info.anchor.into()));
+    commands.insert_resource(SessionSeed(info.seed));
+    if let Some(restart) = info.restart {
+        info!("host restarted the session");
+        restarts.send(restart);
+    }
+    // The handshake connection stays open, the host uses it to tell us about restarts.
+    // GGRS binds to the same port as our discovery socket, so it has to be closed first
+    commands.remove_resource::<ClientDiscovery>();
+    state.set(NetworkingState::InitGgrs);
+}
@@ -0,0 +1,292 @@
+use std::fmt::Write as _;
+
+use bevy::prelude::*;
+use bevy_oxr::xr_input::{
+    hands::{common::HandsResource, HandBone},
+    trackers::{OpenXRLeftEye, OpenXRRightEye, OpenXRTracker},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    discovery::HostDiscovery,
+    handshake::{ClientHandshake, HostHandshake, LobbyRequest, MatchRules},
+    panel::spawn_head_panel,
+    ConnectionSettings, NetworkingState,
+};
+use crate::{speech::RecognizedWord, spell_registry::SpellRegistry};
+
+// Words the lobby listens for, on top of the spell names. The numbers are the handles that can be kicked.
+pub const LOBBY_GRAMMAR: [&str; 8] = [
+    "ready", "start", "kick", "one", "two", "three", "four", "five",
+];
+// How far above the head a palm has to be raised, and for how long, to ready up.
+const RAISE_HEIGHT: f32 = 0.1;
+const RAISE_SECS: f32 = 1.0;
+const PANEL_SIZE: Vec2 = Vec2::new(0.4, 0.3);
+// Straight ahead, it's the only thing to look at before the match starts.
+const PANEL_OFFSET: Vec3 = Vec3::new(0.0, 0.05, -0.8);
+
+// Whether the match has started, the boss and spellcasting wait for it.
+// It's set as the GGRS session starts and never goes back, so rollback systems can depend on it.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum MatchState {
+    #[default]
+    Lobby,
+    Running,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct LobbyPlayer {
+    pub(super) handle: usize,
+    pub(super) ready: bool,
+}
+
+// Everyone in the lobby as far as we know, the host keeps the clients up to date.
+#[derive(Resource, Default)]
+pub(super) struct Lobby {
+    pub(super) players: Vec<LobbyPlayer>,
+    pub(super) spectators: usize,
+    pub(super) local_handle: Option<usize>,
+    pub(super) local_ready: bool,
+    // Set by saying "kick", the next number said is who gets kicked
+    kick_armed: bool,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub(super) enum LobbyAction {
+    // Toggles whether we're ready
+    Ready,
+    Start,
+    Kick(usize),
+}
+
+#[derive(Component, Clone)]
+pub(super) struct LobbyPanel;
+
+#[derive(Component)]
+pub(super) struct LobbyPanelText;
+
+// Holding a palm up above the head for a second readies up, or stops being ready.
+// The palm has to come back down before it counts again.
+pub(super) fn ready_by_gesture(
+    time: Res<Time>,
+    left_eye: Query<&Transform, With<OpenXRLeftEye>>,
+    right_eye: Query<&Transform, With<OpenXRRightEye>>,
+    hand_bones: Query<&Transform, (With<OpenXRTracker>, With<HandBone>)>,
+    hands_resource: Option<Res<HandsResource>>,
+    mut held_secs: Local<f32>,
+    mut actions: EventWriter<LobbyAction>,
+) {
+    let (Ok(left_eye), Ok(right_eye), Some(hands_resource)) = (
+        left_eye.get_single(),
+        right_eye.get_single(),
+        hands_resource,
+    ) else {
+        return;
+    };
+    let head_height = left_eye.translation.lerp(right_eye.translation, 0.5).y;
+    let raised = [hands_resource.left.palm, hands_resource.right.palm]
+        .into_iter()
+        .filter_map(|palm| hand_bones.get(palm).ok())
+        .any(|palm| palm.translation.y > head_height + RAISE_HEIGHT);
+    if !raised {
+        *held_secs = 0.0;
+        return;
+    }
+    let before = *held_secs;
+    *held_secs += time.delta_seconds();
+    if before < RAISE_SECS && *held_secs >= RAISE_SECS {
+        actions.send(LobbyAction::Ready);
+    }
+}
+
+pub(super) fn lobby_voice_command(
+    word: Res<RecognizedWord>,
+    mut lobby: ResMut<Lobby>,
+    mut actions: EventWriter<LobbyAction>,
+) {
+    let kick_armed = std::mem::take(&mut lobby.kick_armed);
+    let number = LOBBY_GRAMMAR[3..].iter().position(|w| *w == word.0);
+    match (&word.0[..], number) {
+        ("ready", _) => {
+            actions.send(LobbyAction::Ready);
+        }
+        ("start", _) => {
+            actions.send(LobbyAction::Start);
+        }
+        ("kick", _) => lobby.kick_armed = true,
+        (_, Some(i)) if kick_armed => {
+            actions.send(LobbyAction::Kick(i + 1));
+        }
+        _ => {}
+    }
+}
+
+// The host's side of the lobby. Starts the match when the host says so, as long as everyone's ready.
+pub(super) fn host_lobby(
+    mut commands: Commands,
+    mut actions: EventReader<LobbyAction>,
+    mut discovery: ResMut<HostDiscovery>,
+    mut handshake: ResMut<HostHandshake>,
+    mut lobby: ResMut<Lobby>,
+    settings: Res<ConnectionSettings>,
+    registry: Res<SpellRegistry>,
+    mut state: ResMut<NextState<NetworkingState>>,
+) {
+    for (handle, request) in handshake.poll_lobby() {
+        match request {
+            LobbyRequest::Ready(ready) => discovery.set_ready(handle, ready),
+            LobbyRequest::Left => {
+                discovery.remove(handle);
+            }
+        }
+    }
+
+    let mut start = false;
+    for action in actions.read() {
+        match *action {
+            LobbyAction::Ready => lobby.local_ready = !lobby.local_ready,
+            LobbyAction::Start => start = true,
+            LobbyAction::Kick(0) => warn!("the host can't kick themselves"),
+            LobbyAction::Kick(handle) => {
+                if discovery.remove(handle) {
+                    info!("kicked player {handle}");
+                    handshake.kick(handle);
+                } else {
+                    warn!("there's no player {handle} to kick");
+                }
+            }
+        }
+    }
+
+    // Clients are only shown once their handshake connection is open, until then we can't tell them anything
+    let mut players = vec![LobbyPlayer {
+        handle: 0,
+        ready: lobby.local_ready,
+    }];
+    players.extend(
+        discovery
+            .roster()
+            .filter(|p| handshake.is_connected(p.handle)),
+    );
+    let spectators = discovery
+        .spectator_handles()
+        .filter(|&h| handshake.is_connected(h))
+        .count();
+    if players != lobby.players || spectators != lobby.spectators {
+        handshake.send_lobby(&players, spectators, MatchRules::new(&settings, &registry));
+        lobby.players = players;
+        lobby.spectators = spectators;
+    }
+
+    if !start {
+        return;
+    }
+    let joined = discovery.roster().count();
+    if joined == 0 {
+        warn!("can't start the match, nobody has joined yet");
+    } else if lobby.players.len() != joined + 1 {
+        warn!("can't start the match, not every player has connected yet");
+    } else if let Some(p) = lobby.players.iter().find(|p| !p.ready) {
+        warn!("can't start the match, player {} isn't ready", p.handle);
+    } else {
+        info!("starting the match with {} players", joined + 1);
+        commands.insert_resource(discovery.start(&mut handshake, &settings));
+        commands.remove_resource::<HostDiscovery>();
+        state.set(NetworkingState::InitGgrs);
+    }
+}
+
+pub(super) fn client_lobby(
+    mut actions: EventReader<LobbyAction>,
+    handshake: Option<ResMut<ClientHandshake>>,
+    mut lobby: ResMut<Lobby>,
+) {
+    let Some(mut handshake) = handshake else {
+        // Lost the host, or were kicked, so we'll be joining a new lobby
+        if lobby.local_handle.is_some() {
+            *lobby = Lobby::default();
+        }
+        actions.clear();
+        return;
+    };
+    for action in actions.read() {
+        match action {
+            LobbyAction::Ready => {
+                lobby.local_ready = !lobby.local_ready;
+                handshake.send_ready(lobby.local_ready);
+            }
+            LobbyAction::Start | LobbyAction::Kick(_) => {
+                warn!("only the host can start the match or kick players")
+            }
+        }
+    }
+}
+
+pub(super) fn spawn_lobby_panel(
+    mut commands: Commands,
+    panels: Query<(), With<LobbyPanel>>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Clients come back to the lobby states whenever they lose the host
+    if !panels.is_empty() {
+        return;
+    }
+    let text = spawn_head_panel(
+        &mut commands,
+        &mut images,
+        &mut meshes,
+        &mut materials,
+        PANEL_SIZE,
+        PANEL_OFFSET,
+        "Looking for a session",
+        LobbyPanel,
+    );
+    commands.entity(text).insert(LobbyPanelText);
+}
+
+pub(super) fn despawn_lobby_panel(mut commands: Commands, panels: Query<Entity, With<LobbyPanel>>) {
+    for e in panels.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+pub(super) fn show_lobby(lobby: Res<Lobby>, mut text: Query<&mut Text, With<LobbyPanelText>>) {
+    let Ok(mut text) = text.get_single_mut() else {
+        return;
+    };
+    let Some(local_handle) = lobby.local_handle else {
+        text.sections[0].value = "Looking for a session".to_owned();
+        return;
+    };
+
+    let mut s = String::from("Lobby\n");
+    for p in lobby.players.iter() {
+        let ready = if p.ready { "ready" } else { "not ready" };
+        let _ = write!(s, "\nPlayer {}: {ready}", p.handle);
+        if p.handle == local_handle {
+            s.push_str(" (you)");
+        }
+    }
+    match lobby.spectators {
+        0 => {}
+        1 => s.push_str("\n1 spectator"),
+        n => {
+            let _ = write!(s, "\n{n} spectators");
+        }
+    }
+    if !lobby.players.iter().any(|p| p.handle == local_handle) {
+        s.push_str("\n\nYou're spectating, the host starts the match");
+        text.sections[0].value = s;
+        return;
+    }
+    s.push_str("\n\nRaise a hand or say \"ready\" to ready up");
+    if local_handle == 0 {
+        s.push_str("\nSay \"start\" to begin, or \"kick\" and a number");
+    } else {
+        s.push_str("\nThe host starts the match");
+    }
+    text.sections[0].value = s;
+}
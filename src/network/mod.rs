@@ -0,0 +1,718 @@
+mod checksum;
+mod desync;
+mod discovery;
+mod events;
+mod hands;
+mod handshake;
+mod lobby;
+mod notifications;
+mod panel;
+mod reconnect;
+mod replay;
+mod rng;
+mod script;
+mod settings;
+mod socket;
+mod stats;
+
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ggrs::{ggrs::DesyncDetection, prelude::*, LocalInputs, LocalPlayers};
+use bevy_oxr::xr_input::{
+    hands::{common::HandsResource, HandBone},
+    trackers::{OpenXRLeftEye, OpenXRRightEye, OpenXRTracker},
+};
+use bevy_xpbd_3d::prelude::*;
+
+pub use self::checksum::{
+    hash_floats, hash_timer, hash_transform, stable_hash, RollbackChecksumApp, StableHasher,
+};
+pub use self::events::{NetworkEvent, SessionRestarted};
+pub use self::hands::{HandSide, PlayerFingerJoint};
+pub use self::lobby::{MatchState, LOBBY_GRAMMAR};
+pub(crate) use self::panel::{spawn_head_panel, spawn_wrist_panel};
+pub use self::rng::RollbackRng;
+pub use self::script::InputScript;
+pub use self::settings::{
+    AimMode, ConnectionSettings, GameMode, SessionMode, SettingsError, MAX_PLAYERS, USAGE,
+};
+use self::settings::{PeerAddr, Role};
+pub use self::stats::{
+    peer_diagnostic_path, NetworkStatsPlugin, AVERAGE_ROLLBACK_DEPTH, ROLLBACKS_PER_SECOND,
+};
+use self::{
+    checksum::ChecksumPlugin,
+    desync::{record_local_inputs, write_desync_reports, InputHistory},
+    discovery::{
+        client_wait, host_answer_rejoins, host_wait, ClientDiscovery, HostDiscovery,
+        RejoinDiscovery, DISCOVERY_PORT,
+    },
+    events::read_ggrs_events,
+    hands::{finger_joints, move_finger_joints, spawn_finger_joints},
+    handshake::{
+        client_await_session, host_accept_clients, host_inform_clients, ClientHandshake,
+        HostHandshake, HANDSHAKE_PORT,
+    },
+    lobby::{
+        client_lobby, despawn_lobby_panel, host_lobby, lobby_voice_command, ready_by_gesture,
+        show_lobby, spawn_lobby_panel, Lobby, LobbyAction,
+    },
+    notifications::{expire_notifications, show_network_notifications, spawn_notification_list},
+    panel::{move_head_panels, move_wrist_panels},
+    reconnect::{
+        client_rejoin_lost_host, despawn_disconnected_players, host_open_rejoins,
+        host_restart_session, restart_session,
+    },
+    replay::{
+        read_replay_inputs, record_inputs, start_recording, write_recording, Recorder, Replay,
+    },
+    script::read_script_inputs,
+    socket::GgrsSocket,
+};
+use crate::{
+    player,
+    speech::{check_fingers_close, RecordingStatus},
+    spell_control::{QueuedSpell, SpellCharge},
+    spell_registry::SpellRegistry,
+    PhysLayer, PlayerInput, WizGgrsConfig, FPS,
+};
+
+// Port the host runs GGRS on, unless told otherwise.
+const DEFAULT_HOST_PORT: u16 = 8000;
+// How far apart the players of a synctest session stand, they all share the one headset's input.
+const SYNCTEST_PLAYER_SPACING: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+
+#[derive(States, Debug, Hash, Eq, PartialEq, Clone)]
+enum NetworkingState {
+    Uninitialized,
+    HostWaiting,
+    ClientWaiting,
+    InitGgrs,
+    Done,
+    // GGRS couldn't be started, there's no getting into a match without restarting the game
+    Failed,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlayerID {
+    pub handle: usize,
+}
+
+#[derive(Resource)]
+pub struct LocalPlayerID {
+    pub handle: usize,
+}
+
+#[derive(Component)]
+pub struct PlayerHead;
+#[derive(Component)]
+pub struct PlayerLeftPalm;
+#[derive(Component)]
+pub struct PlayerRightPalm;
+
+// What we sent last frame, so what's shown to us while aiming matches what every peer will simulate.
+#[derive(Resource, Default, Clone, Copy)]
+pub struct LastLocalInput(pub PlayerInput);
+
+// Pose of the anchor point that every player's coordinates are expressed relative to.
+// The host decides it and sends it to every client during the handshake.
+#[derive(Resource, Default, Clone, Copy)]
+pub struct SharedAnchor(pub Transform);
+
+// Seed for anything random in the simulation, the same on every peer.
+// The host picks it and sends it to every client during the handshake.
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct SessionSeed(pub u64);
+
+impl SessionSeed {
+    fn from_time() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self(now.as_nanos() as u64)
+    }
+}
+
+#[derive(Resource)]
+struct ConnectionArgs {
+    local_port: u16,
+    local_handle: usize,
+    // GGRS address of every player, indexed by handle. The local player's entry isn't used.
+    players: Vec<SocketAddr>,
+    input_delay: usize,
+    max_prediction_window: usize,
+    desync_interval: u32,
+    // GGRS address of every spectator, only known by the host
+    spectators: Vec<SocketAddr>,
+}
+
+impl ConnectionArgs {
+    // Everything but the player list comes from our own settings
+    fn from_settings(
+        settings: &ConnectionSettings,
+        local_port: u16,
+        local_handle: usize,
+        players: Vec<SocketAddr>,
+    ) -> Self {
+        Self {
+            local_port,
+            local_handle,
+            players,
+            input_delay: settings.input_delay,
+            max_prediction_window: settings.max_prediction_window,
+            desync_interval: settings.desync_interval,
+            spectators: Vec::new(),
+        }
+    }
+}
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((GgrsPlugin::<WizGgrsConfig>::default(), ChecksumPlugin))
+            // define frequency of rollback game logic update
+            .set_rollback_schedule_fps(FPS)
+            .rollback_component_with_clone::<Transform>()
+            .checksum_rollback_component::<Transform>(hash_transform)
+            .rollback_component_with_copy::<PlayerID>()
+            .checksum_rollback_component::<PlayerID>(|p| p.handle as u64)
+            .rollback_component_with_copy::<PlayerFingerJoint>()
+            .checksum_rollback_component::<PlayerFingerJoint>(|j| {
+                (j.side as u64) << 32 | j.joint as u64
+            })
+            .init_resource::<RollbackRng>()
+            .rollback_resource_with_copy::<RollbackRng>()
+            .checksum_resource::<RollbackRng>(stable_hash)
+            // Gameplay components are registered by the plugins that own them
+            // TODO remove these systems and have players be instantiated in a different plugin
+            .insert_state(NetworkingState::Uninitialized)
+            .init_resource::<SharedAnchor>()
+            .init_resource::<LastLocalInput>()
+            .init_resource::<SessionSeed>()
+            .init_resource::<ConnectionSettings>()
+            .init_resource::<InputHistory>()
+            .init_resource::<MatchState>()
+            .init_resource::<Lobby>()
+            .add_event::<NetworkEvent>()
+            .add_event::<SessionRestarted>()
+            .add_event::<LobbyAction>()
+            // Clients that lose the host come back here to look for it again
+            .add_systems(OnEnter(NetworkingState::Uninitialized), init)
+            .add_systems(Startup, spawn_notification_list)
+            // Discovery doubles as the lobby, players can join until the host starts the match
+            .add_systems(
+                Update,
+                (host_accept_clients, host_wait, host_lobby)
+                    .chain()
+                    .run_if(in_state(NetworkingState::HostWaiting)),
+            )
+            .add_systems(OnExit(NetworkingState::HostWaiting), host_inform_clients)
+            .add_systems(
+                Update,
+                (
+                    client_wait,
+                    client_await_session.run_if(resource_exists::<ClientHandshake>),
+                    client_lobby,
+                )
+                    .chain()
+                    .run_if(in_state(NetworkingState::ClientWaiting)),
+            )
+            .add_systems(OnEnter(NetworkingState::HostWaiting), spawn_lobby_panel)
+            .add_systems(
+                OnEnter(NetworkingState::ClientWaiting),
+                // Clients that lose the host during the match look for it again, but there's no lobby to show
+                spawn_lobby_panel.run_if(resource_equals(MatchState::Lobby)),
+            )
+            .add_systems(
+                Update,
+                (
+                    ready_by_gesture.run_if(resource_equals(MatchState::Lobby)),
+                    show_lobby.run_if(resource_changed::<Lobby>),
+                    despawn_lobby_panel.run_if(resource_equals(MatchState::Running)),
+                    move_head_panels,
+                    move_wrist_panels,
+                ),
+            )
+            .add_systems(
+                OnEnter(RecordingStatus::Success),
+                lobby_voice_command.run_if(resource_equals(MatchState::Lobby)),
+            )
+            .add_systems(OnEnter(NetworkingState::InitGgrs), init_ggrs)
+            .add_systems(
+                OnEnter(NetworkingState::Done),
+                (
+                    spawn_networked_player_objs,
+                    spawn_finger_joints,
+                    start_recording.run_if(run_once()),
+                ),
+            )
+            .add_systems(
+                ReadInputs,
+                (
+                    read_local_inputs.run_if(
+                        not(resource_exists::<Replay>)
+                            .and_then(not(resource_exists::<InputScript>)),
+                    ),
+                    read_replay_inputs.run_if(resource_exists::<Replay>),
+                    read_script_inputs.run_if(
+                        resource_exists::<InputScript>.and_then(not(resource_exists::<Replay>)),
+                    ),
+                ),
+            )
+            .add_systems(
+                GgrsSchedule,
+                (
+                    despawn_disconnected_players.before(move_networked_player_objs),
+                    move_finger_joints.before(move_networked_player_objs),
+                    move_networked_player_objs,
+                    record_local_inputs,
+                    record_inputs.run_if(resource_exists::<Recorder>),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    read_ggrs_events,
+                    write_recording.run_if(resource_exists::<Recorder>),
+                )
+                    .run_if(resource_exists::<Session<WizGgrsConfig>>),
+            )
+            .add_systems(
+                Update,
+                (
+                    write_desync_reports,
+                    show_network_notifications,
+                    expire_notifications,
+                    client_rejoin_lost_host,
+                )
+                    .after(read_ggrs_events),
+            )
+            // The handshake connections stay open during the match, so a dropped player can be let back in
+            .add_systems(
+                Update,
+                (
+                    host_open_rejoins.run_if(resource_exists::<HostHandshake>),
+                    host_accept_clients.run_if(resource_exists::<RejoinDiscovery>),
+                    host_answer_rejoins.run_if(resource_exists::<RejoinDiscovery>),
+                    host_restart_session.run_if(resource_exists::<RejoinDiscovery>),
+                )
+                    .chain()
+                    .after(read_ggrs_events)
+                    .run_if(in_state(NetworkingState::Done)),
+            )
+            .add_systems(
+                Update,
+                client_await_session
+                    .run_if(resource_exists::<ClientHandshake>)
+                    .run_if(in_state(NetworkingState::Done)),
+            )
+            .add_systems(
+                Update,
+                restart_session
+                    .after(host_restart_session)
+                    .after(client_await_session),
+            );
+    }
+}
+
+fn init(
+    mut commands: Commands,
+    settings: Res<ConnectionSettings>,
+    registry: Res<SpellRegistry>,
+    mut state: ResMut<NextState<NetworkingState>>,
+    mut network_events: EventWriter<NetworkEvent>,
+) {
+    // Here we'll need to create some prompt on startup
+    // This will allow users to select whether they're going to be acting
+    // as the host or a client that will be joining the game
+    // For now the role comes from the connection settings
+    if let Some(path) = &settings.replay {
+        // Every player's inputs come from the recording, so run them all locally
+        let (replay, seed, anchor) = match Replay::open(path, settings.mode, registry.checksum()) {
+            Ok(replay) => replay,
+            Err(e) => {
+                // Replays are only ever run to reproduce something, so fail like a bad command line would,
+                // rather than sit there with nothing to play
+                error!("failed to open replay {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
+        let local_port = settings.local_port.unwrap_or(DEFAULT_HOST_PORT);
+        let players =
+            vec![SocketAddr::from((Ipv4Addr::LOCALHOST, local_port)); replay.num_players()];
+        commands.insert_resource(ConnectionArgs::from_settings(
+            &settings, local_port, 0, players,
+        ));
+        commands.insert_resource(replay);
+        commands.insert_resource(seed);
+        commands.insert_resource(anchor);
+        state.0 = Some(NetworkingState::InitGgrs);
+        return;
+    }
+    if settings.session == SessionMode::SyncTest {
+        // Every player runs in this instance, there's nobody to find
+        let local_port = settings.local_port.unwrap_or(DEFAULT_HOST_PORT);
+        let players =
+            vec![SocketAddr::from((Ipv4Addr::LOCALHOST, local_port)); settings.num_players];
+        commands.insert_resource(ConnectionArgs::from_settings(
+            &settings, local_port, 0, players,
+        ));
+        state.0 = Some(NetworkingState::InitGgrs);
+        return;
+    }
+    match settings.role {
+        Role::Host => {
+            let discovery = match HostDiscovery::new("wizARds".to_owned(), settings.num_players) {
+                Ok(discovery) => discovery,
+                Err(e) => {
+                    socket_failed(
+                        "discovery socket",
+                        DISCOVERY_PORT,
+                        e,
+                        &mut network_events,
+                        &mut state,
+                    );
+                    return;
+                }
+            };
+            let handshake = match HostHandshake::new() {
+                Ok(handshake) => handshake,
+                Err(e) => {
+                    socket_failed(
+                        "handshake listener",
+                        HANDSHAKE_PORT,
+                        e,
+                        &mut network_events,
+                        &mut state,
+                    );
+                    return;
+                }
+            };
+            commands.insert_resource(Lobby {
+                local_handle: Some(0),
+                ..default()
+            });
+            commands.insert_resource(discovery);
+            commands.insert_resource(handshake);
+            commands.insert_resource(SessionSeed::from_time());
+            state.0 = Some(NetworkingState::HostWaiting);
+        }
+        Role::Client | Role::Spectator => {
+            let spectator = settings.role == Role::Spectator;
+            let port = settings.local_port.unwrap_or(0);
+            let discovery = match ClientDiscovery::new(port, spectator) {
+                Ok(discovery) => discovery,
+                Err(e) => {
+                    socket_failed("discovery socket", port, e, &mut network_events, &mut state);
+                    return;
+                }
+            };
+            commands.insert_resource(discovery);
+            state.0 = Some(NetworkingState::ClientWaiting);
+        }
+        Role::Direct => {
+            // Peers are already known, so skip straight to starting GGRS
+            let local_port = settings.local_port.unwrap_or(DEFAULT_HOST_PORT);
+            let local_handle = settings.local_handle().expect("validated on load");
+            let players = settings
+                .players
+                .iter()
+                .map(|p| match p {
+                    PeerAddr::Local => SocketAddr::from((Ipv4Addr::LOCALHOST, local_port)),
+                    PeerAddr::Remote(addr) => *addr,
+                })
+                .collect();
+            commands.insert_resource(ConnectionArgs::from_settings(
+                &settings,
+                local_port,
+                local_handle,
+                players,
+            ));
+            state.0 = Some(NetworkingState::InitGgrs);
+        }
+    }
+}
+
+fn init_ggrs(
+    mut commands: Commands,
+    args: Res<ConnectionArgs>,
+    settings: Res<ConnectionSettings>,
+    seed: Res<SessionSeed>,
+    mut match_state: ResMut<MatchState>,
+    mut state: ResMut<NextState<NetworkingState>>,
+    mut network_events: EventWriter<NetworkEvent>,
+) {
+    // Every peer has the host's seed by now, and a restarted session draws from the start of it again
+    commands.insert_resource(RollbackRng::new(*seed));
+
+    // Once everyone has information about the clients that are going to be playing
+    // We can go ahead and configure and start our Ggrs session
+    let desync_mode = match args.desync_interval {
+        0 => DesyncDetection::Off,
+        interval => DesyncDetection::On { interval },
+    };
+
+    // create a GGRS session
+    let mut sess_build = SessionBuilder::<WizGgrsConfig>::new()
+        .with_num_players(args.players.len())
+        .with_desync_detection_mode(desync_mode)
+        .with_input_delay(args.input_delay)
+        .with_max_prediction_window(args.max_prediction_window)
+        .expect("prediction window can't be 0");
+
+    if settings.session == SessionMode::SyncTest || settings.replay.is_some() {
+        // Every frame gets rolled back and resimulated check_distance frames,
+        // and GGRS compares the checksums of both runs
+        sess_build = sess_build.with_check_distance(settings.check_distance);
+        for i in 0..args.players.len() {
+            sess_build = sess_build
+                .add_player(PlayerType::Local, i)
+                .expect("player handles are always in range");
+        }
+        let sess = sess_build
+            .start_synctest_session()
+            .expect("check distance was validated on load");
+        info!(
+            "started synctest session with {} players, check distance {}",
+            args.players.len(),
+            settings.check_distance
+        );
+        commands.insert_resource(LocalPlayerID {
+            handle: args.local_handle,
+        });
+        commands.insert_resource(Session::SyncTest(sess));
+        *match_state = MatchState::Running;
+        state.0 = Some(NetworkingState::Done);
+        return;
+    }
+
+    let socket = match GgrsSocket::bind(settings.relay, &settings.relay_room, &args) {
+        Ok(socket) => socket,
+        Err(e) => {
+            let port = args.local_port;
+            socket_failed("GGRS socket", port, e, &mut network_events, &mut state);
+            return;
+        }
+    };
+    // The match runs from the first session on, restarts included
+    *match_state = MatchState::Running;
+
+    if settings.role == Role::Spectator {
+        // Spectators just receive the host's confirmed inputs, the host is always handle 0
+        let sess = sess_build.start_spectator_session(args.players[0], socket);
+        commands.insert_resource(Session::Spectator(sess));
+        state.0 = Some(NetworkingState::Done);
+        return;
+    }
+
+    // add players
+    for (i, player_addr) in args.players.iter().enumerate() {
+        let player_type = if i == args.local_handle {
+            PlayerType::Local
+        } else {
+            PlayerType::Remote(*player_addr)
+        };
+        sess_build = sess_build
+            .add_player(player_type, i)
+            .expect("player handles are always in range");
+    }
+    for (i, spectator_addr) in args.spectators.iter().enumerate() {
+        sess_build = sess_build
+            .add_player(
+                PlayerType::Spectator(*spectator_addr),
+                args.players.len() + i,
+            )
+            .expect("spectator handles are always after player handles");
+    }
+    commands.insert_resource(LocalPlayerID {
+        handle: args.local_handle,
+    });
+
+    // start the GGRS session
+    let sess = sess_build
+        .start_p2p_session(socket)
+        .expect("session settings were validated on load");
+
+    // add your GGRS session
+    commands.insert_resource(Session::P2P(sess));
+    state.0 = Some(NetworkingState::Done);
+}
+
+// Most likely something else has the port, another instance say, which trying again won't fix.
+fn socket_failed(
+    socket: &str,
+    port: u16,
+    error: impl Display,
+    network_events: &mut EventWriter<NetworkEvent>,
+    state: &mut NextState<NetworkingState>,
+) {
+    error!("failed to bind {socket} to port {port}: {error}");
+    network_events.send(NetworkEvent::SocketFailed {
+        port,
+        error: error.to_string(),
+    });
+    state.0 = Some(NetworkingState::Failed);
+}
+
+pub fn read_local_inputs(
+    mut commands: Commands,
+    left_eye: Query<&Transform, With<OpenXRLeftEye>>,
+    right_eye: Query<&Transform, With<OpenXRRightEye>>,
+    hand_bones: Query<&Transform, (With<OpenXRTracker>, With<HandBone>)>,
+    hands_resource: Res<HandsResource>,
+    local_player: Res<LocalPlayers>,
+    anchor: Res<SharedAnchor>,
+    settings: Res<ConnectionSettings>,
+    mut queued_spell: ResMut<QueuedSpell>,
+    charge: Res<SpellCharge>,
+) {
+    let mut local_inputs = HashMap::new();
+    let left_eye = left_eye.get_single().unwrap();
+    let right_eye = right_eye.get_single().unwrap();
+    let left_hand = hand_bones.get(hands_resource.left.palm).unwrap();
+    let right_hand = hand_bones.get(hands_resource.right.palm).unwrap();
+
+    // Inputs are sent relative to the shared anchor, so every peer agrees on where things are
+    let to_anchor = anchor.0.compute_affine().inverse();
+    let anchor_rot = anchor.0.rotation.inverse();
+    let mut input = PlayerInput::default();
+    input.set_head(
+        to_anchor.transform_point3(left_eye.translation.lerp(right_eye.translation, 0.5)),
+        anchor_rot * left_eye.rotation,
+    );
+    input.set_left_hand(
+        to_anchor.transform_point3(left_hand.translation),
+        anchor_rot * left_hand.rotation,
+    );
+    input.set_right_hand(
+        to_anchor.transform_point3(right_hand.translation),
+        anchor_rot * right_hand.rotation,
+    );
+    if settings.hand_skeletons {
+        let joint_pos = |e| to_anchor.transform_point3(hand_bones.get(e).unwrap().translation);
+        input.set_hand_joints(
+            &finger_joints(&hands_resource.left).map(joint_pos),
+            &finger_joints(&hands_resource.right).map(joint_pos),
+        );
+    }
+    input.set_aim_mode(settings.aim);
+    input.set_holding(!check_fingers_close(&hand_bones, &hands_resource));
+    if let Some(spell) = queued_spell.0 {
+        input.set_spell(spell);
+        input.set_charge(charge.0);
+    }
+    // Normally there's one local player, but a synctest session makes every player local.
+    // Those are spread out in a line so their spells can't be mistaken for each other's.
+    let spread = local_player.0.len() > 1;
+    for player in local_player.0.iter() {
+        let offset = if spread {
+            SYNCTEST_PLAYER_SPACING * *player as f32
+        } else {
+            Vec3::ZERO
+        };
+        local_inputs.insert(*player, input.translated(offset));
+    }
+    commands.insert_resource(LocalInputs::<WizGgrsConfig>(local_inputs));
+    commands.insert_resource(LastLocalInput(input));
+    queued_spell.0 = None;
+}
+
+fn spawn_networked_player_objs(mut commands: Commands, args: Res<ConnectionArgs>) {
+    // Add one cube on each player's head
+    for i in 0..args.players.len() {
+        commands
+            .spawn((
+                RigidBody::Kinematic,
+                Collider::sphere(0.1),
+                CollisionLayers::new(
+                    PhysLayer::Player,
+                    LayerMask::ALL ^ PhysLayer::PlayerProjectile,
+                ),
+                TransformBundle { ..default() },
+                PlayerID { handle: i },
+                PlayerHead,
+                player::Player,
+            ))
+            .add_rollback();
+        commands
+            .spawn((
+                RigidBody::Kinematic,
+                Collider::sphere(0.1),
+                CollisionLayers::new(
+                    PhysLayer::Player,
+                    LayerMask::ALL ^ PhysLayer::PlayerProjectile,
+                ),
+                TransformBundle { ..default() },
+                PlayerID { handle: i },
+                PlayerLeftPalm,
+            ))
+            .add_rollback();
+        commands
+            .spawn((
+                RigidBody::Kinematic,
+                Collider::sphere(0.1),
+                CollisionLayers::new(
+                    PhysLayer::Player,
+                    LayerMask::ALL ^ PhysLayer::PlayerProjectile,
+                ),
+                TransformBundle { ..default() },
+                PlayerID { handle: i },
+                PlayerRightPalm,
+            ))
+            .add_rollback();
+    }
+}
+
+pub fn move_networked_player_objs(
+    mut player_heads: Query<
+        (&mut Transform, &PlayerID),
+        (
+            With<PlayerHead>,
+            Without<PlayerLeftPalm>,
+            Without<PlayerRightPalm>,
+            With<Rollback>,
+        ),
+    >,
+    mut player_left_palms: Query<
+        (&mut Transform, &PlayerID),
+        (
+            Without<PlayerHead>,
+            With<PlayerLeftPalm>,
+            Without<PlayerRightPalm>,
+            With<Rollback>,
+        ),
+    >,
+    mut player_right_palms: Query<
+        (&mut Transform, &PlayerID),
+        (
+            Without<PlayerHead>,
+            Without<PlayerLeftPalm>,
+            With<PlayerRightPalm>,
+            With<Rollback>,
+        ),
+    >,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    anchor: Res<SharedAnchor>,
+) {
+    // Inputs are relative to the shared anchor, undo what read_local_inputs did to them
+    let anchor = anchor.0;
+    for (mut t, p) in player_heads.iter_mut() {
+        let input = inputs[p.handle].0;
+        t.translation = anchor.transform_point(input.head_pos());
+        t.rotation = anchor.rotation * input.head_rot();
+    }
+    for (mut t, p) in player_left_palms.iter_mut() {
+        let input = inputs[p.handle].0;
+        t.translation = anchor.transform_point(input.left_hand_pos());
+        t.rotation = anchor.rotation * input.left_hand_rot();
+    }
+    for (mut t, p) in player_right_palms.iter_mut() {
+        let input = inputs[p.handle].0;
+        t.translation = anchor.transform_point(input.right_hand_pos());
+        t.rotation = anchor.rotation * input.right_hand_rot();
+    }
+}
@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+
+use super::NetworkEvent;
+
+// How long a notification stays on screen.
+const NOTIFICATION_SECS: f32 = 5.0;
+
+// Column in the corner of the screen that notifications get stacked in.
+#[derive(Component)]
+pub(super) struct NotificationList;
+
+#[derive(Component)]
+pub(super) struct Notification(Timer);
+
+pub(super) fn spawn_notification_list(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        },
+        NotificationList,
+    ));
+}
+
+// Logs network events and shows them on screen.
+// TODO the UI only shows up on the desktop build, the headset needs something in world space.
+pub(super) fn show_network_notifications(
+    mut commands: Commands,
+    mut events: EventReader<NetworkEvent>,
+    list: Query<Entity, With<NotificationList>>,
+) {
+    let Ok(list) = list.get_single() else {
+        return;
+    };
+    for event in events.read() {
+        let (text, color) = match *event {
+            NetworkEvent::Synchronized { handle } => {
+                info!("connected to player {handle}");
+                (format!("Connected to player {handle}"), Color::WHITE)
+            }
+            NetworkEvent::Interrupted {
+                handle,
+                disconnect_timeout,
+            } => {
+                let secs = disconnect_timeout.as_secs_f32();
+                warn!("lost connection to player {handle}, dropping them in {secs:.1}s");
+                (
+                    format!("Lost connection to player {handle}, dropping them in {secs:.0}s"),
+                    Color::YELLOW,
+                )
+            }
+            NetworkEvent::Resumed { handle } => {
+                info!("connection to player {handle} resumed");
+                (format!("Player {handle} is back"), Color::WHITE)
+            }
+            NetworkEvent::Disconnected { handle } => {
+                warn!("player {handle} disconnected");
+                (format!("Player {handle} disconnected"), Color::RED)
+            }
+            // The desync report has the details
+            NetworkEvent::DesyncDetected { frame, handle, .. } => (
+                format!("Desync with player {handle} at frame {frame}"),
+                Color::RED,
+            ),
+            // Already logged where it happened
+            NetworkEvent::SocketFailed { port, ref error } => (
+                format!("Couldn't open port {port}, no match can be played: {error}"),
+                Color::RED,
+            ),
+        };
+        let notification = commands
+            .spawn((
+                TextBundle::from_section(
+                    text,
+                    TextStyle {
+                        font_size: 24.0,
+                        color,
+                        ..default()
+                    },
+                ),
+                Notification(Timer::from_seconds(NOTIFICATION_SECS, TimerMode::Once)),
+            ))
+            .id();
+        commands.entity(list).add_child(notification);
+    }
+}
+
+pub(super) fn expire_notifications(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut notifications: Query<(Entity, &mut Notification)>,
+) {
+    for (e, mut notification) in notifications.iter_mut() {
+        if notification.0.tick(time.delta()).finished() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
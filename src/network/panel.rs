@@ -0,0 +1,179 @@
+use bevy::{
+    math::primitives::Rectangle,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+    },
+};
+use bevy_oxr::xr_input::{
+    hands::{common::HandsResource, HandBone},
+    trackers::{OpenXRLeftEye, OpenXRRightEye, OpenXRTracker},
+};
+
+// How sharp a panel's text is.
+const PIXELS_PER_METRE: f32 = 1700.0;
+
+// A panel of text that floats at a fixed offset from the head.
+#[derive(Component)]
+pub(super) struct HeadPanel {
+    offset: Vec3,
+}
+
+// A panel of text on the back of the left wrist, like a watch. The offset is in the wrist's space.
+#[derive(Component)]
+pub(super) struct WristPanel {
+    offset: Vec3,
+}
+
+// The panel's text is laid out by the UI into a texture, which is shown on a quad that follows the head.
+// `marker` goes on the quad, its camera and its text, so despawning everything with it removes the panel.
+// Returns the text entity, for whoever's going to be filling it in.
+pub(crate) fn spawn_head_panel(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    size: Vec2,
+    offset: Vec3,
+    text: &str,
+    marker: impl Component + Clone,
+) -> Entity {
+    let follow = HeadPanel { offset };
+    spawn_panel(
+        commands, images, meshes, materials, size, text, marker, follow,
+    )
+}
+
+// Same as a head panel, but it follows the left wrist instead.
+pub(crate) fn spawn_wrist_panel(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    size: Vec2,
+    offset: Vec3,
+    text: &str,
+    marker: impl Component + Clone,
+) -> Entity {
+    let follow = WristPanel { offset };
+    spawn_panel(
+        commands, images, meshes, materials, size, text, marker, follow,
+    )
+}
+
+fn spawn_panel(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    size: Vec2,
+    text: &str,
+    marker: impl Component + Clone,
+    follow: impl Component,
+) -> Entity {
+    let pixels = (size * PIXELS_PER_METRE).as_uvec2();
+    let extent = Extent3d {
+        width: pixels.x,
+        height: pixels.y,
+        ..default()
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: extent,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(extent);
+    let image = images.add(image);
+
+    let camera = commands
+        .spawn((
+            Camera2dBundle {
+                camera: Camera {
+                    target: RenderTarget::Image(image.clone()),
+                    order: -1,
+                    clear_color: ClearColorConfig::Custom(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+                    ..default()
+                },
+                ..default()
+            },
+            marker.clone(),
+        ))
+        .id();
+    let text = commands
+        .spawn((
+            TextBundle::from_section(
+                text,
+                TextStyle {
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                margin: UiRect::all(Val::Px(16.0)),
+                ..default()
+            }),
+            TargetCamera(camera),
+            marker.clone(),
+        ))
+        .id();
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Rectangle::from_size(size)),
+            material: materials.add(StandardMaterial {
+                base_color_texture: Some(image),
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            ..default()
+        },
+        follow,
+        marker,
+    ));
+    text
+}
+
+pub(super) fn move_head_panels(
+    mut panels: Query<(&mut Transform, &HeadPanel)>,
+    left_eye: Query<&Transform, (With<OpenXRLeftEye>, Without<HeadPanel>)>,
+    right_eye: Query<&Transform, (With<OpenXRRightEye>, Without<HeadPanel>)>,
+) {
+    let (Ok(left_eye), Ok(right_eye)) = (left_eye.get_single(), right_eye.get_single()) else {
+        return;
+    };
+    let head_pos = left_eye.translation.lerp(right_eye.translation, 0.5);
+    for (mut t, panel) in panels.iter_mut() {
+        t.translation = head_pos + left_eye.rotation * panel.offset;
+        t.rotation = left_eye.rotation;
+    }
+}
+
+pub(super) fn move_wrist_panels(
+    mut panels: Query<(&mut Transform, &WristPanel)>,
+    hand_bones: Query<&Transform, (With<OpenXRTracker>, With<HandBone>, Without<WristPanel>)>,
+    hands_resource: Option<Res<HandsResource>>,
+) {
+    let Some(wrist) = hands_resource.and_then(|h| hand_bones.get(h.left.wrist).ok()) else {
+        return;
+    };
+    // The back of the hand is up in the wrist's space, so face the panel that way
+    let rotation = wrist.rotation * Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2);
+    for (mut t, panel) in panels.iter_mut() {
+        t.translation = wrist.translation + wrist.rotation * panel.offset;
+        t.rotation = rotation;
+    }
+}
@@ -0,0 +1,159 @@
+use bevy::prelude::*;
+use bevy_ggrs::{ggrs::InputStatus, prelude::*, PlayerInputs};
+
+use super::{
+    discovery::RejoinDiscovery,
+    handshake::{send_session_info, ClientHandshake, HostHandshake, MatchRules},
+    replay::Recorder,
+    settings::Role,
+    ConnectionArgs, ConnectionSettings, NetworkEvent, NetworkingState, PlayerFingerJoint,
+    PlayerHead, PlayerID, PlayerLeftPalm, PlayerRightPalm, SessionRestarted, SessionSeed,
+    SharedAnchor,
+};
+use crate::{boss::CurrentPhase, duel::DuelScore, spell_registry::SpellRegistry, WizGgrsConfig};
+
+// GGRS keeps predicting a disconnected player's last input, so remove them instead of leaving them frozen.
+// Every peer sees the disconnect on the same frame, so this is as deterministic as the rest of the simulation.
+pub(super) fn despawn_disconnected_players(
+    mut commands: Commands,
+    players: Query<
+        (Entity, &PlayerID),
+        Or<(
+            With<PlayerHead>,
+            With<PlayerLeftPalm>,
+            With<PlayerRightPalm>,
+            With<PlayerFingerJoint>,
+        )>,
+    >,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+) {
+    for (e, p) in players.iter() {
+        if inputs[p.handle].1 == InputStatus::Disconnected {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
+
+// Opens the session back up when a player drops out, so they can rejoin.
+pub(super) fn host_open_rejoins(
+    mut commands: Commands,
+    mut events: EventReader<NetworkEvent>,
+    args: Res<ConnectionArgs>,
+    mut handshake: ResMut<HostHandshake>,
+    discovery: Option<ResMut<RejoinDiscovery>>,
+) {
+    // Spectators can't rejoin, so there's nothing to hold open for them
+    let dropped: Vec<usize> = events
+        .read()
+        .filter_map(|e| match *e {
+            NetworkEvent::Disconnected { handle } if handle < args.players.len() => Some(handle),
+            _ => None,
+        })
+        .collect();
+    if dropped.is_empty() {
+        return;
+    }
+
+    let mut new_discovery = None;
+    let discovery = match discovery {
+        Some(discovery) => discovery.into_inner(),
+        None => match RejoinDiscovery::new("wizARds".to_owned(), args.players.len()) {
+            Ok(discovery) => new_discovery.insert(discovery),
+            Err(e) => {
+                error!("failed to bind discovery socket, dropped players can't rejoin: {e}");
+                return;
+            }
+        },
+    };
+    for handle in dropped {
+        info!("waiting for player {handle} to rejoin");
+        discovery.vacate(handle);
+        handshake.disconnect(handle);
+    }
+    if let Some(discovery) = new_discovery {
+        commands.insert_resource(discovery);
+    }
+}
+
+// Once every dropped player is back, tell everyone to start a new session with them in it.
+pub(super) fn host_restart_session(
+    mut commands: Commands,
+    discovery: Res<RejoinDiscovery>,
+    mut handshake: ResMut<HostHandshake>,
+    mut args: ResMut<ConnectionArgs>,
+    anchor: Res<SharedAnchor>,
+    seed: Res<SessionSeed>,
+    settings: Res<ConnectionSettings>,
+    registry: Res<SpellRegistry>,
+    // Only one of these is around, depending on the mode
+    phase: Option<Res<CurrentPhase>>,
+    duel_score: Option<Res<DuelScore>>,
+    mut restarts: EventWriter<SessionRestarted>,
+    mut state: ResMut<NextState<NetworkingState>>,
+) {
+    let Some(rejoined) = discovery.rejoined(&handshake) else {
+        return;
+    };
+    for (handle, addr) in rejoined {
+        args.players[handle] = addr;
+    }
+    info!("every dropped player is back, restarting the session");
+    let restart = SessionRestarted {
+        boss_phase: phase.map(|p| p.0).unwrap_or_default(),
+        duel_score: duel_score.map(|s| *s).unwrap_or_default(),
+    };
+    send_session_info(
+        &mut handshake,
+        &args,
+        *anchor,
+        *seed,
+        MatchRules::new(&settings, &registry),
+        Some(restart),
+    );
+    restarts.send(restart);
+    commands.remove_resource::<RejoinDiscovery>();
+    state.set(NetworkingState::InitGgrs);
+}
+
+// Without the host there's no session left to play in, so go back to looking for it.
+// If it's still around it'll have opened our handle back up for us.
+pub(super) fn client_rejoin_lost_host(
+    mut commands: Commands,
+    mut events: EventReader<NetworkEvent>,
+    settings: Res<ConnectionSettings>,
+    mut state: ResMut<NextState<NetworkingState>>,
+) {
+    let lost_host = events
+        .read()
+        .any(|e| matches!(e, NetworkEvent::Disconnected { handle: 0 }));
+    if !lost_host {
+        return;
+    }
+    if settings.role != Role::Client {
+        warn!("lost connection to the host");
+        return;
+    }
+    warn!("lost connection to the host, trying to rejoin");
+    commands.remove_resource::<Session<WizGgrsConfig>>();
+    commands.remove_resource::<ClientHandshake>();
+    commands.remove_resource::<Recorder>();
+    state.set(NetworkingState::Uninitialized);
+}
+
+// Every peer starts the new session from the same state, so there's nothing for a rejoining player to catch up on.
+pub(super) fn restart_session(
+    mut commands: Commands,
+    mut restarts: EventReader<SessionRestarted>,
+    rollback_entities: Query<Entity, With<Rollback>>,
+) {
+    if restarts.read().last().is_none() {
+        return;
+    }
+    // The old session has to be gone before the new one binds its socket
+    commands.remove_resource::<Session<WizGgrsConfig>>();
+    // A recording only covers the first session
+    commands.remove_resource::<Recorder>();
+    for e in rollback_entities.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use bevy::{app::AppExit, prelude::*, utils::HashMap};
+use bevy_ggrs::{prelude::*, LocalInputs, PlayerInputs, RollbackFrameCount};
+use serde::{Deserialize, Serialize};
+
+use super::{ConnectionArgs, ConnectionSettings, GameMode, SessionSeed, SharedAnchor};
+use crate::{spell_registry::SpellRegistry, PlayerInput, WizGgrsConfig, FPS};
+
+// Every recording starts with this, so we don't try to replay some other file.
+const REPLAY_MAGIC: u32 = u32::from_be_bytes(*b"WIZR");
+// Bump this whenever `ReplayHeader` or `PlayerInput` change shape.
+const REPLAY_VERSION: u32 = 5;
+
+// Written once at the start of a recording, followed by every player's input for each frame in order.
+#[derive(Serialize, Deserialize, Debug)]
+struct ReplayHeader {
+    magic: u32,
+    version: u32,
+    fps: u32,
+    seed: u64,
+    num_players: u32,
+    mode: GameMode,
+    // Checksum of the spell registry, the same inputs cast different spells with different spells
+    spells: u64,
+    anchor_translation: [f32; 3],
+    anchor_rotation: [f32; 4],
+}
+
+impl ReplayHeader {
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let header: ReplayHeader = bincode::deserialize_from(reader)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        if header.magic != REPLAY_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a wizARds recording",
+            ));
+        }
+        if header.version != REPLAY_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "recording has version {}, we can only replay version {REPLAY_VERSION}",
+                    header.version
+                ),
+            ));
+        }
+        if header.num_players == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "recording has no players",
+            ));
+        }
+        if header.fps as usize != FPS {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("recording ran at {} fps but we run at {FPS}", header.fps),
+            ));
+        }
+        Ok(header)
+    }
+}
+
+// Writes the confirmed inputs of every player to a file as the match goes on.
+#[derive(Resource)]
+pub(super) struct Recorder {
+    writer: BufWriter<File>,
+    num_players: usize,
+    // Frames that have been simulated but might still be rolled back
+    pending: BTreeMap<i32, Vec<PlayerInput>>,
+}
+
+impl Recorder {
+    fn create(
+        path: &Path,
+        num_players: usize,
+        mode: GameMode,
+        spells: u64,
+        seed: SessionSeed,
+        anchor: SharedAnchor,
+    ) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = ReplayHeader {
+            magic: REPLAY_MAGIC,
+            version: REPLAY_VERSION,
+            fps: FPS as u32,
+            seed: seed.0,
+            num_players: num_players as u32,
+            mode,
+            spells,
+            anchor_translation: anchor.0.translation.to_array(),
+            anchor_rotation: anchor.0.rotation.to_array(),
+        };
+        bincode::serialize_into(&mut writer, &header).map_err(io::Error::other)?;
+        Ok(Self {
+            writer,
+            num_players,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    // Writes out every pending frame up to and including `confirmed_frame`.
+    fn write_confirmed(&mut self, confirmed_frame: i32) -> io::Result<()> {
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > confirmed_frame {
+                break;
+            }
+            for input in entry.remove().iter() {
+                self.writer.write_all(bytemuck::bytes_of(input))?;
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+pub(super) fn start_recording(
+    mut commands: Commands,
+    settings: Res<ConnectionSettings>,
+    args: Res<ConnectionArgs>,
+    seed: Res<SessionSeed>,
+    anchor: Res<SharedAnchor>,
+    registry: Res<SpellRegistry>,
+) {
+    let Some(path) = &settings.record else {
+        return;
+    };
+    let recorder = Recorder::create(
+        path,
+        args.players.len(),
+        settings.mode,
+        registry.checksum(),
+        *seed,
+        *anchor,
+    );
+    match recorder {
+        Ok(recorder) => {
+            info!("recording inputs to {}", path.display());
+            commands.insert_resource(recorder);
+        }
+        Err(e) => error!("failed to start recording to {}: {e}", path.display()),
+    }
+}
+
+pub(super) fn record_inputs(
+    mut recorder: ResMut<Recorder>,
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+) {
+    let frame_inputs = (0..recorder.num_players).map(|h| inputs[h].0).collect();
+    // Resimulating a frame replaces the inputs it was predicted with
+    recorder.pending.insert(**frame, frame_inputs);
+}
+
+pub(super) fn write_recording(
+    mut commands: Commands,
+    mut recorder: ResMut<Recorder>,
+    session: Res<Session<WizGgrsConfig>>,
+    frame: Res<RollbackFrameCount>,
+) {
+    // Only a P2P session can roll back frames with new inputs, the others never mispredict
+    let confirmed_frame = match &*session {
+        Session::P2P(session) => session.confirmed_frame(),
+        Session::SyncTest(_) | Session::Spectator(_) => **frame,
+    };
+    if let Err(e) = recorder.write_confirmed(confirmed_frame) {
+        error!("failed to write recording, stopping: {e}");
+        commands.remove_resource::<Recorder>();
+    }
+}
+
+// Inputs of a recorded match, fed back to a local session one frame at a time.
+#[derive(Resource)]
+pub(super) struct Replay {
+    reader: BufReader<File>,
+    num_players: usize,
+}
+
+impl Replay {
+    // Reads the header, and returns the session it describes alongside the replay itself.
+    // The mode decides which plugins were added, so it can't be taken from the recording.
+    pub(super) fn open(
+        path: &Path,
+        mode: GameMode,
+        spells: u64,
+    ) -> io::Result<(Self, SessionSeed, SharedAnchor)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header = ReplayHeader::read(&mut reader)?;
+        if header.mode != mode {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "recording is of a {:?} match but we're running {mode:?}, set --mode to match",
+                    header.mode
+                ),
+            ));
+        }
+        if header.spells != spells {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "recording was made with different spells to ours",
+            ));
+        }
+        let anchor = Transform::from_translation(Vec3::from_array(header.anchor_translation))
+            .with_rotation(Quat::from_array(header.anchor_rotation));
+        let replay = Self {
+            reader,
+            num_players: header.num_players as usize,
+        };
+        Ok((replay, SessionSeed(header.seed), SharedAnchor(anchor)))
+    }
+
+    pub(super) fn num_players(&self) -> usize {
+        self.num_players
+    }
+
+    // Returns None once the recording has run out.
+    fn next_frame(&mut self) -> io::Result<Option<Vec<PlayerInput>>> {
+        let mut inputs = Vec::with_capacity(self.num_players);
+        for _ in 0..self.num_players {
+            let mut input = PlayerInput::default();
+            match self.reader.read_exact(bytemuck::bytes_of_mut(&mut input)) {
+                Ok(()) => inputs.push(input),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(inputs))
+    }
+}
+
+pub(super) fn read_replay_inputs(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let inputs = match replay.next_frame() {
+        Ok(Some(inputs)) => inputs,
+        // GGRS still needs inputs for this frame, even though we're about to exit
+        Ok(None) => {
+            info!("replay finished");
+            exit.send(AppExit);
+            vec![PlayerInput::default(); replay.num_players]
+        }
+        // Whatever's been replayed so far can't be trusted to be what was recorded
+        Err(e) => {
+            error!("failed to read replay: {e}");
+            std::process::exit(1);
+        }
+    };
+    let local_inputs: HashMap<_, _> = inputs.into_iter().enumerate().collect();
+    commands.insert_resource(LocalInputs::<WizGgrsConfig>(local_inputs));
+}
@@ -0,0 +1,42 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+
+use super::SessionSeed;
+
+// Randomness for the simulation. It's seeded from the session seed and rolled back with everything else,
+// so every peer draws the same numbers on the same frame, and a resimulated frame draws them again.
+// Only draw from it in the rollback schedule, anything else drawing from it would put the peers out of step.
+// A build warns about thread_rng in files with rollback systems, it's different on every peer.
+#[derive(Resource, Default, Clone, Copy, Debug, Hash)]
+pub struct RollbackRng {
+    state: u64,
+}
+
+impl RollbackRng {
+    pub fn new(seed: SessionSeed) -> Self {
+        Self { state: seed.0 }
+    }
+
+    // SplitMix64, which is tiny, fast, and plenty random enough for a game
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // Uniform in [0, 1). 24 random bits scaled down exactly, so it's the same on every platform.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    pub fn range_f32(&mut self, range: Range<f32>) -> f32 {
+        range.start + self.next_f32() * (range.end - range.start)
+    }
+}
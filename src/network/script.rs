@@ -0,0 +1,24 @@
+use bevy::prelude::*;
+use bevy_ggrs::{LocalInputs, LocalPlayers};
+
+use crate::{PlayerInput, WizGgrsConfig};
+
+// Drives the local players from a function of their handle and the frame, instead of a headset.
+// Used by the test harness to run whole sessions without anyone wearing one.
+#[derive(Resource, Clone, Copy)]
+pub struct InputScript(pub fn(usize, u32) -> PlayerInput);
+
+pub(super) fn read_script_inputs(
+    mut commands: Commands,
+    script: Res<InputScript>,
+    local_players: Res<LocalPlayers>,
+    mut frame: Local<u32>,
+) {
+    let local_inputs = local_players
+        .0
+        .iter()
+        .map(|&handle| (handle, (script.0)(handle, *frame)))
+        .collect();
+    *frame += 1;
+    commands.insert_resource(LocalInputs::<WizGgrsConfig>(local_inputs));
+}
@@ -0,0 +1,492 @@
+use std::{
+    fmt::{self, Display},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Config file that's read if no other path is given.
+const DEFAULT_CONFIG_PATH: &str = "wizards.ron";
+// Prefix for every environment variable override, e.g. WIZARDS_LOCAL_PORT.
+const ENV_PREFIX: &str = "WIZARDS_";
+// More input delay than this (~0.4s at 72 fps) makes the game unplayable.
+const MAX_INPUT_DELAY: usize = 30;
+// The most players a session can have, the boss's difficulty is tuned up to this many.
+pub const MAX_PLAYERS: usize = 6;
+
+pub const USAGE: &str = "\
+Options (each can also be set in the config file, or with a WIZARDS_<OPTION> environment variable):
+    --config <path>                 RON config file to load [default: wizards.ron]
+    --role <host|client|spectator|direct>
+                                    host and client find each other over LAN, a spectator
+                                    watches the host's session, direct uses --players
+    --num-players <n>               most players the host's lobby lets in, up to 6
+    --local-port <port>             port to run GGRS on
+    --players <addr,addr,...>       GGRS address of every player, only used with --role direct
+    --local-player <index>          which entry of --players is this instance
+    --input-delay <frames>          frames of delay added to local inputs
+    --max-prediction-window <frames> how far ahead of confirmed inputs we may simulate
+    --desync-interval <frames>      how often to compare checksums with peers, 0 to disable
+    --desync-dump-dir <path>        where to write a report when checksums don't match [default: .]
+    --session <p2p|synctest>        synctest runs every player locally and checks rollbacks are deterministic
+    --check-distance <frames>       how many frames a synctest session rolls back each frame
+    --record <path>                 write every player's inputs to a file, to replay the match later
+    --replay <path>                 replay a recorded match in a local session, then exit
+    --stats-panel <true|false>      show ping, frame advantage and rollbacks next to your head
+    --hand-skeletons <true|false>   send every finger joint instead of just the palms, so others see your hands
+    --relay <addr>                  send GGRS traffic through the relay server at this ip:port, for networks
+                                    that won't let peers talk to each other directly
+    --relay-room <name>             room on the relay server, every peer in a session has to use the same one [default: wizARds]
+    --mode <boss|duel>              fight the boss together, or each other [default: boss]
+    --aim <head|palms|shoulder>     aim spells where you look, where your palms face, or from your
+                                    shoulders through your hands [default: head]";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Host,
+    Client,
+    Spectator,
+    Direct,
+}
+
+impl FromStr for Role {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "host" => Ok(Role::Host),
+            "client" => Ok(Role::Client),
+            "spectator" => Ok(Role::Spectator),
+            "direct" => Ok(Role::Direct),
+            _ => Err("expected one of host, client, spectator or direct".to_owned()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    P2P,
+    SyncTest,
+}
+
+impl FromStr for SessionMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "p2p" => Ok(SessionMode::P2P),
+            "synctest" => Ok(SessionMode::SyncTest),
+            _ => Err("expected one of p2p or synctest".to_owned()),
+        }
+    }
+}
+
+// What the match is. Every peer has to agree, clients won't join a host playing the other one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GameMode {
+    // Everyone against the boss
+    #[default]
+    Boss,
+    // Everyone against each other, over a series of rounds
+    Duel,
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "boss" => Ok(GameMode::Boss),
+            "duel" => Ok(GameMode::Duel),
+            _ => Err("expected one of boss or duel".to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GameMode::Boss => "boss",
+            GameMode::Duel => "duel",
+        })
+    }
+}
+
+// How a player aims their spells. Each player picks their own, it's sent along with their input.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AimMode {
+    // Wherever the head is facing
+    #[default]
+    Head,
+    // Out of the front of the palms, like pushing the spell away
+    Palms,
+    // From the shoulders through the palms, like throwing it
+    Shoulder,
+}
+
+impl FromStr for AimMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "head" => Ok(AimMode::Head),
+            "palms" => Ok(AimMode::Palms),
+            "shoulder" => Ok(AimMode::Shoulder),
+            _ => Err("expected one of head, palms or shoulder".to_owned()),
+        }
+    }
+}
+
+// An entry in the player list, either this instance or the GGRS address of a peer.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum PeerAddr {
+    Local,
+    Remote(SocketAddr),
+}
+
+impl FromStr for PeerAddr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "localhost" => Ok(PeerAddr::Local),
+            s => s
+                .parse()
+                .map(PeerAddr::Remote)
+                .map_err(|e| format!("\"{s}\" isn't \"localhost\" or an ip:port address ({e})")),
+        }
+    }
+}
+
+impl TryFrom<String> for PeerAddr {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Resource, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionSettings {
+    pub role: Role,
+    pub num_players: usize,
+    // Hosts default to 8000, clients to the port their discovery socket was given
+    pub local_port: Option<u16>,
+    pub players: Vec<PeerAddr>,
+    // Defaults to the "localhost" entry of `players`
+    pub local_player: Option<usize>,
+    pub input_delay: usize,
+    pub max_prediction_window: usize,
+    pub desync_interval: u32,
+    pub desync_dump_dir: PathBuf,
+    pub session: SessionMode,
+    pub check_distance: usize,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub stats_panel: bool,
+    pub hand_skeletons: bool,
+    pub relay: Option<SocketAddr>,
+    pub relay_room: String,
+    pub mode: GameMode,
+    pub aim: AimMode,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            role: Role::Host,
+            num_players: 2,
+            local_port: None,
+            players: Vec::new(),
+            local_player: None,
+            input_delay: 2,
+            max_prediction_window: 12,
+            desync_interval: 10,
+            desync_dump_dir: PathBuf::from("."),
+            session: SessionMode::P2P,
+            check_distance: 2,
+            record: None,
+            replay: None,
+            stats_panel: false,
+            hand_skeletons: false,
+            relay: None,
+            relay_room: "wizARds".to_owned(),
+            mode: GameMode::Boss,
+            aim: AimMode::Head,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    File {
+        path: PathBuf,
+        error: ron::error::SpannedError,
+    },
+    Value {
+        source: String,
+        key: String,
+        value: String,
+        reason: String,
+    },
+    UnknownArgument(String),
+    Invalid(String),
+    HelpRequested,
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io { path, error } => {
+                write!(f, "couldn't read config file {}: {error}", path.display())
+            }
+            SettingsError::File { path, error } => {
+                write!(f, "invalid config file {}: {error}", path.display())
+            }
+            SettingsError::Value {
+                source,
+                key,
+                value,
+                reason,
+            } => write!(
+                f,
+                "invalid value \"{value}\" for {key} from {source}: {reason}"
+            ),
+            SettingsError::UnknownArgument(arg) => {
+                write!(f, "unknown argument \"{arg}\"\n\n{USAGE}")
+            }
+            SettingsError::Invalid(reason) => write!(f, "invalid connection settings: {reason}"),
+            SettingsError::HelpRequested => write!(f, "{USAGE}"),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+fn parse_value<T: FromStr>(source: &str, key: &str, value: &str) -> Result<T, SettingsError>
+where
+    T::Err: Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e: T::Err| SettingsError::Value {
+            source: source.to_owned(),
+            key: key.to_owned(),
+            value: value.to_owned(),
+            reason: e.to_string(),
+        })
+}
+
+impl ConnectionSettings {
+    // Defaults, overridden by the config file, then environment variables, then command line arguments.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self, SettingsError> {
+        let args = parse_args(args)?;
+
+        let config_path = args
+            .iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| PathBuf::from(value))
+            .or_else(|| std::env::var_os(format!("{ENV_PREFIX}CONFIG")).map(PathBuf::from));
+        let mut settings = match config_path {
+            Some(path) => Self::read_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::read_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        for key in KEYS {
+            let var = format!("{ENV_PREFIX}{}", key.to_ascii_uppercase().replace('-', "_"));
+            if let Ok(value) = std::env::var(&var) {
+                settings.set(&format!("environment variable {var}"), key, &value)?;
+            }
+        }
+        for (key, value) in args.iter().filter(|(key, _)| key != "config") {
+            settings.set("the command line", key, value)?;
+        }
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SettingsError> {
+        let settings = Self::read_file(path)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn read_file(path: &Path) -> Result<Self, SettingsError> {
+        let contents = std::fs::read_to_string(path).map_err(|error| SettingsError::Io {
+            path: path.to_owned(),
+            error,
+        })?;
+        ron::from_str(&contents).map_err(|error| SettingsError::File {
+            path: path.to_owned(),
+            error,
+        })
+    }
+
+    fn set(&mut self, source: &str, key: &str, value: &str) -> Result<(), SettingsError> {
+        match key {
+            "role" => self.role = parse_value(source, key, value)?,
+            "num-players" => self.num_players = parse_value(source, key, value)?,
+            "local-port" => self.local_port = Some(parse_value(source, key, value)?),
+            "players" => {
+                self.players = value
+                    .split(',')
+                    .filter(|p| !p.trim().is_empty())
+                    .map(|p| parse_value(source, key, p))
+                    .collect::<Result<_, _>>()?
+            }
+            "local-player" => self.local_player = Some(parse_value(source, key, value)?),
+            "input-delay" => self.input_delay = parse_value(source, key, value)?,
+            "max-prediction-window" => {
+                self.max_prediction_window = parse_value(source, key, value)?
+            }
+            "desync-interval" => self.desync_interval = parse_value(source, key, value)?,
+            "desync-dump-dir" => self.desync_dump_dir = PathBuf::from(value),
+            "session" => self.session = parse_value(source, key, value)?,
+            "check-distance" => self.check_distance = parse_value(source, key, value)?,
+            "record" => self.record = Some(PathBuf::from(value)),
+            "replay" => self.replay = Some(PathBuf::from(value)),
+            "stats-panel" => self.stats_panel = parse_value(source, key, value)?,
+            "hand-skeletons" => self.hand_skeletons = parse_value(source, key, value)?,
+            "relay" => self.relay = Some(parse_value(source, key, value)?),
+            "relay-room" => self.relay_room = value.to_owned(),
+            "mode" => self.mode = parse_value(source, key, value)?,
+            "aim" => self.aim = parse_value(source, key, value)?,
+            _ => return Err(SettingsError::UnknownArgument(format!("--{key}"))),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), SettingsError> {
+        let invalid = |reason: String| Err(SettingsError::Invalid(reason));
+
+        if self.local_port == Some(0) {
+            return invalid("local-port can't be 0".to_owned());
+        }
+        if self.max_prediction_window == 0 {
+            return invalid("max-prediction-window can't be 0".to_owned());
+        }
+        if self.num_players > MAX_PLAYERS || self.players.len() > MAX_PLAYERS {
+            return invalid(format!(
+                "a session can't have more than {MAX_PLAYERS} players"
+            ));
+        }
+        if self.input_delay > MAX_INPUT_DELAY {
+            return invalid(format!(
+                "input-delay of {} frames is more than the maximum of {MAX_INPUT_DELAY}",
+                self.input_delay
+            ));
+        }
+        if self.session == SessionMode::SyncTest || self.replay.is_some() {
+            // Every player is local, so the role and player list don't matter
+            if self.num_players == 0 && self.replay.is_none() {
+                return invalid("num-players can't be 0".to_owned());
+            }
+            if self.check_distance == 0 || self.check_distance >= self.max_prediction_window {
+                return invalid(format!(
+                    "check-distance must be between 1 and max-prediction-window - 1 ({})",
+                    self.max_prediction_window - 1
+                ));
+            }
+            return Ok(());
+        }
+        match self.role {
+            Role::Host if self.num_players == 0 => invalid("num-players can't be 0".to_owned()),
+            Role::Host | Role::Client | Role::Spectator => Ok(()),
+            Role::Direct => {
+                if self.players.is_empty() {
+                    return invalid("role direct needs at least one entry in players".to_owned());
+                }
+                let Some(local) = self.local_handle() else {
+                    return invalid(
+                        "role direct needs local-player, or a \"localhost\" entry in players"
+                            .to_owned(),
+                    );
+                };
+                if local >= self.players.len() {
+                    return invalid(format!(
+                        "local-player is {local} but there are only {} players",
+                        self.players.len()
+                    ));
+                }
+                let locals = self.players.iter().filter(|p| **p == PeerAddr::Local);
+                if locals.count() > 1 {
+                    return invalid("only one entry in players can be \"localhost\"".to_owned());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn local_handle(&self) -> Option<usize> {
+        self.local_player
+            .or_else(|| self.players.iter().position(|p| *p == PeerAddr::Local))
+    }
+}
+
+// Every option that can be set from the environment or command line.
+const KEYS: &[&str] = &[
+    "role",
+    "num-players",
+    "local-port",
+    "players",
+    "local-player",
+    "input-delay",
+    "max-prediction-window",
+    "desync-interval",
+    "desync-dump-dir",
+    "session",
+    "check-distance",
+    "record",
+    "replay",
+    "stats-panel",
+    "hand-skeletons",
+    "relay",
+    "relay-room",
+    "mode",
+    "aim",
+];
+
+// Splits `--key value` and `--key=value` arguments into pairs.
+fn parse_args(
+    args: impl IntoIterator<Item = String>,
+) -> Result<Vec<(String, String)>, SettingsError> {
+    let mut pairs = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Err(SettingsError::HelpRequested);
+        }
+        let Some(arg) = arg.strip_prefix("--") else {
+            return Err(SettingsError::UnknownArgument(arg));
+        };
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key.to_owned(), value.to_owned()),
+            None => {
+                let value = args.next().ok_or_else(|| SettingsError::Value {
+                    source: "the command line".to_owned(),
+                    key: arg.to_owned(),
+                    value: String::new(),
+                    reason: "missing value".to_owned(),
+                })?;
+                (arg.to_owned(), value)
+            }
+        };
+        if key != "config" && !KEYS.contains(&key.as_str()) {
+            return Err(SettingsError::UnknownArgument(format!("--{key}")));
+        }
+        pairs.push((key, value));
+    }
+    Ok(pairs)
+}
@@ -0,0 +1,153 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy_ggrs::ggrs::{Message, NonBlockingSocket, UdpNonBlockingSocket};
+
+use super::ConnectionArgs;
+use crate::relay::{RelayPacket, MAX_RELAY_PACKET, RELAY_VERSION};
+
+// How often we tell the relay we're still here.
+const JOIN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Sessions are generic over their socket, this lets the settings pick one at runtime.
+pub(super) enum GgrsSocket {
+    Direct(UdpNonBlockingSocket),
+    Relay(RelaySocket),
+}
+
+impl GgrsSocket {
+    pub(super) fn bind(
+        relay: Option<SocketAddr>,
+        room: &str,
+        args: &ConnectionArgs,
+    ) -> io::Result<Self> {
+        match relay {
+            Some(relay) => {
+                info!("sending GGRS traffic through relay {relay}, room {room}");
+                RelaySocket::bind(relay, room.to_owned(), args).map(Self::Relay)
+            }
+            None => UdpNonBlockingSocket::bind_to_port(args.local_port).map(Self::Direct),
+        }
+    }
+}
+
+impl NonBlockingSocket<SocketAddr> for GgrsSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        match self {
+            Self::Direct(socket) => socket.send_to(msg, addr),
+            Self::Relay(socket) => socket.send_to(msg, addr),
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        match self {
+            Self::Direct(socket) => socket.receive_all_messages(),
+            Self::Relay(socket) => socket.receive_all_messages(),
+        }
+    }
+}
+
+// Sends GGRS packets through a relay server instead of straight to the peers.
+// GGRS still addresses peers by their usual addresses, the relay only knows them by handle,
+// so packets are translated between the two on the way in and out.
+pub(super) struct RelaySocket {
+    socket: UdpSocket,
+    relay: SocketAddr,
+    join: Vec<u8>,
+    last_join: Option<Instant>,
+    // GGRS address of every handle, players then spectators, None for our own
+    peers: Vec<Option<SocketAddr>>,
+}
+
+impl RelaySocket {
+    fn bind(relay: SocketAddr, room: String, args: &ConnectionArgs) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, args.local_port))?;
+        socket.set_nonblocking(true)?;
+        let join = bincode::serialize(&RelayPacket::Join {
+            version: RELAY_VERSION,
+            room,
+            handle: args.local_handle,
+        })
+        .map_err(io::Error::other)?;
+        let peers = (0..args.players.len() + args.spectators.len())
+            .map(|h| {
+                (h != args.local_handle).then(|| match args.players.get(h) {
+                    Some(addr) => *addr,
+                    None => args.spectators[h - args.players.len()],
+                })
+            })
+            .collect();
+        Ok(Self {
+            socket,
+            relay,
+            join,
+            last_join: None,
+            peers,
+        })
+    }
+
+    fn keep_joined(&mut self) {
+        if self.last_join.is_some_and(|t| t.elapsed() < JOIN_INTERVAL) {
+            return;
+        }
+        if let Err(e) = self.socket.send_to(&self.join, self.relay) {
+            warn!("failed to reach relay {}: {e}", self.relay);
+        }
+        self.last_join = Some(Instant::now());
+    }
+}
+
+impl NonBlockingSocket<SocketAddr> for RelaySocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        self.keep_joined();
+        let Some(handle) = self.peers.iter().position(|p| *p == Some(*addr)) else {
+            warn!("GGRS sent a packet to {addr}, who isn't in the session");
+            return;
+        };
+        let packet = bincode::serialize(msg)
+            .and_then(|payload| bincode::serialize(&RelayPacket::Forward { handle, payload }));
+        match packet {
+            // Like any UDP socket, a lost packet is GGRS's problem
+            Ok(bytes) => {
+                let _ = self.socket.send_to(&bytes, self.relay);
+            }
+            Err(e) => error!("failed to encode GGRS packet: {e}"),
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.keep_joined();
+        let mut messages = Vec::new();
+        let mut buf = [0; MAX_RELAY_PACKET];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows reports an earlier send to a closed port as an error on the next receive
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    warn!("failed to receive from relay: {e}");
+                    break;
+                }
+            };
+            if from != self.relay {
+                continue;
+            }
+            let Ok(RelayPacket::Deliver { handle, payload }) = bincode::deserialize(&buf[..len])
+            else {
+                continue;
+            };
+            let Some(Some(addr)) = self.peers.get(handle) else {
+                continue;
+            };
+            if let Ok(msg) = bincode::deserialize(&payload) {
+                messages.push((*addr, msg));
+            }
+        }
+        messages
+    }
+}
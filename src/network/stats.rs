@@ -0,0 +1,204 @@
+use std::fmt::Write as _;
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticMeasurement, DiagnosticPath, DiagnosticsStore},
+    prelude::*,
+    utils::Instant,
+};
+use bevy_ggrs::{prelude::*, GgrsSchedule, RollbackFrameCount};
+
+use super::{panel::spawn_head_panel, ConnectionSettings, SessionRestarted};
+use crate::WizGgrsConfig;
+
+// GGRS only updates its stats about once a second, so there's no point asking more often.
+const POLL_INTERVAL_SECS: f32 = 1.0;
+// The panel's size in metres.
+const PANEL_SIZE: Vec2 = Vec2::new(0.3, 0.15);
+// Where the panel sits relative to the head, down and to the left so it's out of the way.
+const PANEL_OFFSET: Vec3 = Vec3::new(-0.3, -0.2, -0.6);
+
+pub const ROLLBACKS_PER_SECOND: DiagnosticPath =
+    DiagnosticPath::const_new("network/rollbacks_per_second");
+pub const AVERAGE_ROLLBACK_DEPTH: DiagnosticPath =
+    DiagnosticPath::const_new("network/average_rollback_depth");
+
+// Peers aren't known up front, so their diagnostics are added as they show up,
+// named network/player<handle>/<stat>.
+pub fn peer_diagnostic_path(handle: usize, stat: &str) -> DiagnosticPath {
+    DiagnosticPath::new(format!("network/player{handle}/{stat}"))
+}
+
+// Records connection quality as diagnostics, so LogDiagnosticsPlugin prints it with everything else.
+pub struct NetworkStatsPlugin;
+
+impl Plugin for NetworkStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RollbackCounter>()
+            .insert_resource(StatsPollTimer(Timer::from_seconds(
+                POLL_INTERVAL_SECS,
+                TimerMode::Repeating,
+            )))
+            .add_systems(Startup, spawn_stats_panel)
+            .add_systems(GgrsSchedule, count_rollbacks)
+            .add_systems(
+                Update,
+                (
+                    reset_rollback_counter,
+                    record_network_stats.run_if(resource_exists::<Session<WizGgrsConfig>>),
+                ),
+            );
+    }
+}
+
+// Not a rollback resource, it's counting the rollbacks themselves.
+#[derive(Resource, Default)]
+struct RollbackCounter {
+    // Last frame the rollback schedule simulated
+    last_frame: Option<i32>,
+    rollbacks: u32,
+    resimulated_frames: u32,
+}
+
+#[derive(Resource)]
+struct StatsPollTimer(Timer);
+
+#[derive(Component, Clone)]
+struct StatsPanel;
+
+#[derive(Component)]
+struct StatsPanelText;
+
+fn count_rollbacks(frame: Res<RollbackFrameCount>, mut counter: ResMut<RollbackCounter>) {
+    let frame = **frame;
+    // Simulating a frame we've already been past means GGRS loaded an older snapshot
+    if let Some(last_frame) = counter.last_frame.filter(|&last| frame <= last) {
+        counter.rollbacks += 1;
+        counter.resimulated_frames += (last_frame - frame + 1) as u32;
+    }
+    counter.last_frame = Some(frame);
+}
+
+// A restarted session counts frames from 0 again, which isn't a rollback
+fn reset_rollback_counter(
+    mut restarts: EventReader<SessionRestarted>,
+    mut counter: ResMut<RollbackCounter>,
+) {
+    if restarts.read().last().is_some() {
+        *counter = RollbackCounter::default();
+    }
+}
+
+fn measure(store: &mut DiagnosticsStore, path: &DiagnosticPath, suffix: &'static str, value: f64) {
+    if store.get(path).is_none() {
+        store.add(Diagnostic::new(path.clone()).with_suffix(suffix));
+    }
+    if let Some(diagnostic) = store.get_mut(path) {
+        diagnostic.add_measurement(DiagnosticMeasurement {
+            time: Instant::now(),
+            value,
+        });
+    }
+}
+
+fn record_network_stats(
+    time: Res<Time<Real>>,
+    mut timer: ResMut<StatsPollTimer>,
+    mut counter: ResMut<RollbackCounter>,
+    session: Res<Session<WizGgrsConfig>>,
+    mut store: ResMut<DiagnosticsStore>,
+    mut panel: Query<&mut Text, With<StatsPanelText>>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let mut summary = String::new();
+
+    // Stats aren't available until we've synchronized with a peer
+    let peer_stats: Vec<_> = match &*session {
+        Session::P2P(session) => session
+            .remote_player_handles()
+            .into_iter()
+            .filter_map(|h| session.network_stats(h).ok().map(|stats| (h, stats)))
+            .collect(),
+        // A spectator only talks to the host
+        Session::Spectator(session) => session
+            .network_stats()
+            .ok()
+            .map(|stats| (0, stats))
+            .into_iter()
+            .collect(),
+        Session::SyncTest(_) => Vec::new(),
+    };
+    for (handle, stats) in peer_stats {
+        // Positive when we're behind the peer, negative when we're ahead
+        let frames_behind = stats.local_frames_behind;
+        measure(
+            &mut store,
+            &peer_diagnostic_path(handle, "rtt"),
+            "ms",
+            stats.ping as f64,
+        );
+        measure(
+            &mut store,
+            &peer_diagnostic_path(handle, "frames_behind"),
+            "",
+            frames_behind as f64,
+        );
+        let _ = writeln!(
+            summary,
+            "Player {handle}: {}ms, {frames_behind:+} frames",
+            stats.ping
+        );
+    }
+
+    let rollbacks_per_second = counter.rollbacks as f32 / timer.0.duration().as_secs_f32();
+    let average_depth = match counter.rollbacks {
+        0 => 0.0,
+        n => counter.resimulated_frames as f32 / n as f32,
+    };
+    measure(
+        &mut store,
+        &ROLLBACKS_PER_SECOND,
+        "/s",
+        rollbacks_per_second as f64,
+    );
+    measure(
+        &mut store,
+        &AVERAGE_ROLLBACK_DEPTH,
+        " frames",
+        average_depth as f64,
+    );
+    let _ = write!(
+        summary,
+        "Rollbacks: {rollbacks_per_second:.1}/s, {average_depth:.1} frames deep"
+    );
+    counter.rollbacks = 0;
+    counter.resimulated_frames = 0;
+
+    for mut text in panel.iter_mut() {
+        text.sections[0].value.clone_from(&summary);
+    }
+}
+
+fn spawn_stats_panel(
+    mut commands: Commands,
+    settings: Res<ConnectionSettings>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !settings.stats_panel {
+        return;
+    }
+    let text = spawn_head_panel(
+        &mut commands,
+        &mut images,
+        &mut meshes,
+        &mut materials,
+        PANEL_SIZE,
+        PANEL_OFFSET,
+        "Waiting for the session",
+        StatsPanel,
+    );
+    commands.entity(text).insert(StatsPanelText);
+}
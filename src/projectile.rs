@@ -1,20 +1,76 @@
+use std::hash::Hasher;
+
 use bevy::prelude::*;
-use bevy_ggrs::{AddRollbackCommandExtension, GgrsSchedule};
+use bevy_ggrs::{AddRollbackCommandExtension, GgrsApp, GgrsSchedule, PlayerInputs};
 use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     assets::{AssetHandles, MatName, MeshName},
-    boss::{BossHealth, BossPhase},
-    network::{move_networked_player_objs, PlayerID},
-    PhysLayer,
+    boss::{Boss, BossHealth, BossPhase, NextPhase},
+    duel::PlayerHealth,
+    network::{
+        hash_floats, move_networked_player_objs, PlayerHead, PlayerID, PlayerLeftPalm,
+        PlayerRightPalm, RollbackChecksumApp, StableHasher,
+    },
+    spell_control::palms_facing,
+    PhysLayer, WizGgrsConfig,
 };
 
 pub enum ProjectileType {
-    Fireball,
-    LightningBolt,
+    // Anything a player casts, as described by its spell
+    Spell(ProjectileParams),
     BossAttack,
 }
 
+// How a player's projectile looks, flies and hurts, from the spell registry.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ProjectileParams {
+    pub mesh: MeshName,
+    pub material: MatName,
+    // Size relative to the mesh, the collider is scaled along with it
+    pub scale: f32,
+    // Metres per second
+    pub speed: f32,
+    pub damage: Damage,
+    // Any of these can be combined, projectiles without them fly straight
+    #[serde(default)]
+    pub homing: Option<Homing>,
+    #[serde(default)]
+    pub gravity: Option<Gravity>,
+    #[serde(default)]
+    pub weave: Option<Weave>,
+    #[serde(default)]
+    pub palm_steered: Option<PalmSteered>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DamageType {
+    Fire,
+    Lightning,
+}
+
+impl From<DamageType> for DamageMask {
+    fn from(t: DamageType) -> Self {
+        match t {
+            DamageType::Fire => DamageMask::FIRE,
+            DamageType::Lightning => DamageMask::LIGHTNING,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Damage {
+    pub kind: DamageType,
+    pub amount: f32,
+}
+
+impl From<Damage> for DamageHit {
+    fn from(d: Damage) -> Self {
+        DamageHit(d.kind.into(), d.amount)
+    }
+}
+
 #[derive(Component)]
 struct ProjectileHit(Entity);
 
@@ -23,9 +79,39 @@ pub struct DamageHit(pub DamageMask, pub f32);
 
 #[derive(Component, Debug, Clone)]
 pub struct ResetPhaseHit;
-#[derive(Debug, Default, Component)]
+#[derive(Debug, Default, Component, Clone, Copy)]
 pub struct LinearMovement(f32);
 
+// Turns towards the nearest thing it can hurt, at up to `turn_rate` degrees a second.
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Homing {
+    pub turn_rate: f32,
+}
+
+// Falls as it flies, speeding up by `strength` metres per second every second.
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Gravity {
+    pub strength: f32,
+}
+
+// Weaves from side to side of the line it's flying along.
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Weave {
+    // Metres either side
+    pub amplitude: f32,
+    // Times a second
+    pub frequency: f32,
+    #[serde(skip)]
+    elapsed: f32,
+}
+
+// Follows where its caster's palms face while they keep holding it, at up to `turn_rate` degrees a second.
+// They let go by touching their fingertips together, and can't take hold of it again after.
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PalmSteered {
+    pub turn_rate: f32,
+}
+
 #[derive(Debug, Component, Clone)]
 pub enum ProjectileHitEffect {
     Damage(DamageHit),
@@ -37,9 +123,13 @@ impl Default for ProjectileHitEffect {
     }
 }
 
-#[derive(Component, Debug, Default)]
+#[derive(Component, Debug, Default, Clone, Copy)]
 pub struct Projectile;
 
+// Handle of the player whose spell a projectile came from.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Caster(pub usize);
+
 // DamageMask struct used for handling damage types.
 // Each bit is a damage type, a bit is set to 1 if that type is enabled.
 // Things that deal damage should have a damage mask with the damage types they deal enabled.
@@ -66,16 +156,46 @@ pub struct ProjectilePlugin;
 impl Plugin for ProjectilePlugin {
     fn build(&self, app: &mut App) {
         // All Projectile code needs to run on the GgrsSchedule.
-        app.add_systems(
-            GgrsSchedule,
-            (
-                update_linear_movement.ambiguous_with(move_networked_player_objs), // TODO this might be a hack, but also might be how bevy_ggrs works
-                detect_projectile_collisions,
-                handle_damage_hits,
-                handle_reset_phase_hits,
-            )
-                .chain(),
-        );
+        app.rollback_component_with_copy::<Projectile>()
+            .rollback_component_with_copy::<Caster>()
+            .checksum_rollback_component::<Caster>(|c| c.0 as u64)
+            .rollback_component_with_copy::<LinearMovement>()
+            .checksum_rollback_component::<LinearMovement>(|m| hash_floats(&[m.0]))
+            .rollback_component_with_copy::<Homing>()
+            .checksum_rollback_component::<Homing>(|h| hash_floats(&[h.turn_rate]))
+            .rollback_component_with_copy::<Gravity>()
+            .checksum_rollback_component::<Gravity>(|g| hash_floats(&[g.strength]))
+            .rollback_component_with_copy::<Weave>()
+            .checksum_rollback_component::<Weave>(|w| {
+                hash_floats(&[w.amplitude, w.frequency, w.elapsed])
+            })
+            .rollback_component_with_copy::<PalmSteered>()
+            .checksum_rollback_component::<PalmSteered>(|p| hash_floats(&[p.turn_rate]))
+            .rollback_component_with_clone::<ProjectileHitEffect>()
+            .checksum_rollback_component::<ProjectileHitEffect>(|e| match e {
+                ProjectileHitEffect::Damage(DamageHit(mask, damage)) => {
+                    let mut hasher = StableHasher::default();
+                    hasher.write_u64(hash_floats(&[*damage]));
+                    hasher.write_u8(mask.0);
+                    hasher.finish()
+                }
+                ProjectileHitEffect::ResetPhase(_) => 0,
+            })
+            .add_systems(
+                GgrsSchedule,
+                (
+                    // Steering goes first, so the projectile flies the way it's turned this frame
+                    update_homing.after(move_networked_player_objs),
+                    update_palm_steering.after(move_networked_player_objs),
+                    update_gravity,
+                    update_weave,
+                    update_linear_movement.ambiguous_with(move_networked_player_objs), // TODO this might be a hack, but also might be how bevy_ggrs works
+                    detect_projectile_collisions,
+                    handle_damage_hits,
+                    handle_reset_phase_hits,
+                )
+                    .chain(),
+            );
     }
 }
 
@@ -90,6 +210,122 @@ pub fn update_linear_movement(
     }
 }
 
+// Turns `t` towards `dir`, but by no more than `max_angle` radians.
+fn turn_towards(t: &mut Transform, dir: Vec3, max_angle: f32) {
+    let forward = Vec3::from(t.forward());
+    let angle = forward.angle_between(dir);
+    if angle <= f32::EPSILON {
+        return;
+    }
+    let turn = Quat::from_rotation_arc(forward, dir);
+    t.rotation = Quat::IDENTITY.slerp(turn, (max_angle / angle).min(1.0)) * t.rotation;
+}
+
+// Player spells go for the boss, or in a duel anyone but their caster. The boss's attacks go for any player.
+fn update_homing(
+    time: Res<Time>,
+    mut projectiles: Query<
+        (
+            &mut Transform,
+            &Homing,
+            &ProjectileHitEffect,
+            Option<&Caster>,
+        ),
+        With<Projectile>,
+    >,
+    bosses: Query<&Transform, (With<Boss>, Without<Projectile>)>,
+    heads: Query<
+        (&Transform, &PlayerID, Has<PlayerHealth>),
+        (With<PlayerHead>, Without<Projectile>),
+    >,
+) {
+    for (mut t, homing, effect, caster) in projectiles.iter_mut() {
+        let targets: Vec<Vec3> = match effect {
+            ProjectileHitEffect::Damage(_) => bosses
+                .iter()
+                .map(|b| b.translation)
+                .chain(
+                    heads
+                        .iter()
+                        .filter(|(_, id, has_health)| {
+                            *has_health && caster.map_or(true, |c| c.0 != id.handle)
+                        })
+                        .map(|(head, _, _)| head.translation),
+                )
+                .collect(),
+            ProjectileHitEffect::ResetPhase(_) => {
+                heads.iter().map(|(head, _, _)| head.translation).collect()
+            }
+        };
+        let here = t.translation;
+        let nearest = targets.into_iter().min_by(|a, b| {
+            a.distance_squared(here)
+                .total_cmp(&b.distance_squared(here))
+        });
+        if let Some(dir) = nearest.and_then(|target| (target - here).try_normalize()) {
+            let max_angle = homing.turn_rate.to_radians() * time.delta_seconds();
+            turn_towards(&mut t, dir, max_angle);
+        }
+    }
+}
+
+fn update_palm_steering(
+    mut commands: Commands,
+    time: Res<Time>,
+    inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    mut projectiles: Query<(Entity, &mut Transform, &PalmSteered, &Caster), With<Projectile>>,
+    left_palms: Query<(&Transform, &PlayerID), (With<PlayerLeftPalm>, Without<Projectile>)>,
+    right_palms: Query<(&Transform, &PlayerID), (With<PlayerRightPalm>, Without<Projectile>)>,
+) {
+    for (e, mut t, steered, caster) in projectiles.iter_mut() {
+        if !inputs[caster.0].0.holding() {
+            // Let go of, so it carries on the way it was last pointed
+            commands.entity(e).remove::<PalmSteered>();
+            continue;
+        }
+        let left = left_palms.iter().find(|(_, id)| id.handle == caster.0);
+        let right = right_palms.iter().find(|(_, id)| id.handle == caster.0);
+        let (Some((left, _)), Some((right, _))) = (left, right) else {
+            continue;
+        };
+        if let Some(dir) = palms_facing(left.rotation, right.rotation) {
+            let max_angle = steered.turn_rate.to_radians() * time.delta_seconds();
+            turn_towards(&mut t, dir, max_angle);
+        }
+    }
+}
+
+// Pitches the projectile down as it falls, so it flies along the arc.
+fn update_gravity(
+    time: Res<Time>,
+    mut projectiles: Query<(&mut Transform, &mut LinearMovement, &Gravity), With<Projectile>>,
+) {
+    for (mut t, mut movement, gravity) in projectiles.iter_mut() {
+        let velocity =
+            t.forward() * movement.0 + Vec3::NEG_Y * gravity.strength * time.delta_seconds();
+        if let Some(dir) = velocity.try_normalize() {
+            t.rotation = Quat::from_rotation_arc(t.forward().into(), dir) * t.rotation;
+            movement.0 = velocity.length();
+        }
+    }
+}
+
+fn update_weave(
+    time: Res<Time>,
+    mut projectiles: Query<(&mut Transform, &mut Weave), With<Projectile>>,
+) {
+    for (mut t, mut weave) in projectiles.iter_mut() {
+        let offset = |elapsed: f32| {
+            weave.amplitude * (elapsed * weave.frequency * std::f32::consts::TAU).sin()
+        };
+        let before = offset(weave.elapsed);
+        let after = offset(weave.elapsed + time.delta_seconds());
+        let right = t.right();
+        t.translation += right * (after - before);
+        weave.elapsed += time.delta_seconds();
+    }
+}
+
 // Check for collisions between projectiles and other objects, and emit entities to represent these "hits".
 fn detect_projectile_collisions(
     mut commands: Commands,
@@ -156,60 +392,42 @@ fn handle_damage_hits(
 }
 
 // Handle hits from boss projectiles.
-fn handle_reset_phase_hits(
+pub fn handle_reset_phase_hits(
     mut commands: Commands,
     hits: Query<(&Transform, &ProjectileHit, Entity), With<ResetPhaseHit>>,
-    mut next_phase: ResMut<NextState<BossPhase>>,
+    // There's no boss in a duel
+    mut next_phase: Option<ResMut<NextPhase>>,
     players: Query<&PlayerID>,
 ) {
     for (_transform, p_hit, e) in hits.iter() {
         // Check if the collided entity is part of a player.
-        if players.get(p_hit.0).is_ok() {
-            next_phase.set(BossPhase::Reset)
+        if let (Ok(_), Some(next_phase)) = (players.get(p_hit.0), next_phase.as_mut()) {
+            next_phase.0 = Some(BossPhase::Reset)
         }
         commands.entity(e).despawn();
     }
 }
 
 // Given a projectile type, spawn a corresponding projectile. Sort of prefabing.
+// Movement components like `Homing` can be added to the returned entity on top of whatever it came with.
 pub fn spawn_projectile(
     commands: &mut Commands,
     projectile_type: ProjectileType,
     spell_transform: &Transform,
     asset_handles: &Res<AssetHandles>,
-) {
+) -> Entity {
     match projectile_type {
-        ProjectileType::Fireball => commands
-            .spawn((
-                Projectile,
-                PbrBundle {
-                    mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                    material: asset_handles.mats[MatName::Red as usize].clone(),
-                    transform: *spell_transform,
-                    ..Default::default()
-                },
-                LinearMovement(3.0),
-                ProjectileHitEffect::Damage(DamageHit(DamageMask::FIRE, 25.0)),
-                CollisionLayers::new(
-                    PhysLayer::PlayerProjectile,
-                    ((LayerMask::ALL ^ PhysLayer::Player) ^ PhysLayer::BossProjectile)
-                        ^ PhysLayer::ParryObject,
-                ),
-                Collider::sphere(0.1),
-                RigidBody::Kinematic,
-            ))
-            .add_rollback(),
-        ProjectileType::LightningBolt => commands
-            .spawn((
+        ProjectileType::Spell(params) => {
+            let mut projectile = commands.spawn((
                 Projectile,
                 PbrBundle {
-                    mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                    material: asset_handles.mats[MatName::Blue as usize].clone(),
-                    transform: *spell_transform,
+                    mesh: asset_handles.meshes[params.mesh as usize].clone(),
+                    material: asset_handles.mats[params.material as usize].clone(),
+                    transform: spell_transform.with_scale(params.scale * Vec3::ONE),
                     ..Default::default()
                 },
-                LinearMovement(6.0),
-                ProjectileHitEffect::Damage(DamageHit(DamageMask::LIGHTNING, 25.0)),
+                LinearMovement(params.speed),
+                ProjectileHitEffect::Damage(params.damage.into()),
                 CollisionLayers::new(
                     PhysLayer::PlayerProjectile,
                     ((LayerMask::ALL ^ PhysLayer::Player) ^ PhysLayer::BossProjectile)
@@ -217,8 +435,21 @@ pub fn spawn_projectile(
                 ),
                 Collider::sphere(0.1),
                 RigidBody::Kinematic,
-            ))
-            .add_rollback(),
+            ));
+            if let Some(homing) = params.homing {
+                projectile.insert(homing);
+            }
+            if let Some(gravity) = params.gravity {
+                projectile.insert(gravity);
+            }
+            if let Some(weave) = params.weave {
+                projectile.insert(weave);
+            }
+            if let Some(steered) = params.palm_steered {
+                projectile.insert(steered);
+            }
+            projectile.add_rollback().id()
+        }
         ProjectileType::BossAttack => commands
             .spawn((
                 Projectile,
@@ -237,6 +468,7 @@ pub fn spawn_projectile(
                 Collider::sphere(0.2),
                 RigidBody::Kinematic,
             ))
-            .add_rollback(),
-    };
+            .add_rollback()
+            .id(),
+    }
 }
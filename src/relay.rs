@@ -0,0 +1,186 @@
+// A tiny UDP relay for GGRS traffic, for networks that won't let peers talk to each other directly,
+// like Wi-Fi with client isolation. Every peer sends its packets to the relay, addressed by handle,
+// and the relay passes them on to whichever socket last joined the room as that handle.
+//
+//     cargo run --bin relay -- --port 7000
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+// Bumped whenever the packets change, so mismatched builds don't misread each other.
+pub const RELAY_VERSION: u32 = 1;
+pub const DEFAULT_RELAY_PORT: u16 = 7000;
+// GGRS packets are well under this, the rest is our own framing.
+pub const MAX_RELAY_PACKET: usize = 4096;
+// Peers rejoin every second, so one that's been quiet this long has gone.
+const ROUTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const USAGE: &str = "\
+Usage: relay [options]
+    --port <port>   UDP port to listen on [default: 7000]";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RelayPacket {
+    // Peer to relay: packets for `handle` in `room` go to the socket this came from.
+    // Sent regularly, which also keeps NAT mappings open.
+    Join {
+        version: u32,
+        room: String,
+        handle: usize,
+    },
+    // Peer to relay: pass this on to `handle` in the sender's room.
+    Forward {
+        handle: usize,
+        payload: Vec<u8>,
+    },
+    // Relay to peer: this came from `handle` in your room.
+    Deliver {
+        handle: usize,
+        payload: Vec<u8>,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct Seat {
+    room: String,
+    handle: usize,
+}
+
+#[derive(Default)]
+struct Routes {
+    seats: HashMap<SocketAddr, (Seat, Instant)>,
+    sockets: HashMap<Seat, SocketAddr>,
+}
+
+impl Routes {
+    fn join(&mut self, from: SocketAddr, seat: Seat) {
+        // A peer that restarted comes back from a new port, so the seat moves with it
+        if let Some(old) = self
+            .sockets
+            .insert(seat.clone(), from)
+            .filter(|old| *old != from)
+        {
+            self.seats.remove(&old);
+        }
+        match self.seats.insert(from, (seat.clone(), Instant::now())) {
+            Some((old, _)) if old == seat => {}
+            // The socket moved to another seat, which nobody's in any more
+            Some((old, _)) => {
+                self.sockets.remove(&old);
+                println!(
+                    "{from} moved to room {} as player {}",
+                    seat.room, seat.handle
+                );
+            }
+            None => println!("{from} joined room {} as player {}", seat.room, seat.handle),
+        }
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let sockets = &mut self.sockets;
+        self.seats.retain(|from, (seat, last_seen)| {
+            let alive = now.duration_since(*last_seen) < ROUTE_TIMEOUT;
+            if !alive {
+                println!("{from} left room {} as player {}", seat.room, seat.handle);
+                sockets.remove(seat);
+            }
+            alive
+        });
+    }
+}
+
+pub fn main() {
+    let mut port = DEFAULT_RELAY_PORT;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next().map(|v| v.parse())) {
+            ("--port", Some(Ok(value))) => port = value,
+            _ => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Err(e) = run(port) {
+        eprintln!("relay stopped: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(port: u16) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+    // Wake up now and then to drop peers that have gone, even if nobody's sending anything
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    println!("relaying on {}", socket.local_addr()?);
+
+    let mut routes = Routes::default();
+    let mut last_expired = Instant::now();
+    let mut buf = [0; MAX_RELAY_PACKET];
+    loop {
+        if last_expired.elapsed() >= Duration::from_secs(1) {
+            routes.expire();
+            last_expired = Instant::now();
+        }
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            // Windows reports an earlier send to a closed port as an error on the next receive
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        };
+        let packet: RelayPacket = match bincode::deserialize(&buf[..len]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!("ignoring bad packet from {from}: {e}");
+                continue;
+            }
+        };
+        match packet {
+            RelayPacket::Join {
+                version,
+                room,
+                handle,
+            } => {
+                if version != RELAY_VERSION {
+                    eprintln!(
+                        "{from} is running relay version {version}, expected {RELAY_VERSION}"
+                    );
+                    continue;
+                }
+                routes.join(from, Seat { room, handle });
+            }
+            RelayPacket::Forward { handle, payload } => {
+                // Peers that haven't joined yet, or whose target hasn't, are dropped.
+                // GGRS resends anything that matters.
+                let Some((seat, _)) = routes.seats.get(&from) else {
+                    continue;
+                };
+                let target = Seat {
+                    room: seat.room.clone(),
+                    handle,
+                };
+                let Some(to) = routes.sockets.get(&target) else {
+                    continue;
+                };
+                let deliver = RelayPacket::Deliver {
+                    handle: seat.handle,
+                    payload,
+                };
+                match bincode::serialize(&deliver) {
+                    Ok(bytes) => {
+                        let _ = socket.send_to(&bytes, to);
+                    }
+                    Err(e) => eprintln!("failed to encode packet for {to}: {e}"),
+                }
+            }
+            RelayPacket::Deliver { .. } => eprintln!("{from} sent the relay a delivery"),
+        }
+    }
+}
@@ -173,7 +173,7 @@ fn check_start_recording(
     hands_resource: Res<HandsResource>,
     mut recording_state: ResMut<NextState<RecordingStatus>>,
 ) {
-    if check_fingers_close(hand_bones, &hands_resource) {
+    if check_fingers_close(&hand_bones, &hands_resource) {
         recording_state.set(RecordingStatus::Recording);
     }
 }
@@ -183,7 +183,7 @@ fn check_stop_recording(
     hands_resource: Res<HandsResource>,
     mut recording_state: ResMut<NextState<RecordingStatus>>,
 ) {
-    if !check_fingers_close(hand_bones, &hands_resource) {
+    if !check_fingers_close(&hand_bones, &hands_resource) {
         recording_state.set(RecordingStatus::Awaiting);
     }
 }
@@ -205,7 +205,7 @@ fn check_word_found(
 }
 
 pub(crate) fn check_fingers_close(
-    hand_bones: Query<&Transform, (With<OpenXRTracker>, With<HandBone>)>,
+    hand_bones: &Query<&Transform, (With<OpenXRTracker>, With<HandBone>)>,
     hands_resource: &HandsResource,
 ) -> bool {
     let thumb_dists = (hand_bones
@@ -5,46 +5,30 @@ use bevy_oxr::xr_input::hands::HandBone;
 use bevy_oxr::xr_input::trackers::OpenXRTracker;
 
 use crate::{
-    network::{LocalPlayerID, PlayerHead, PlayerID},
+    boss::Boss,
+    mana::{regen_mana, Mana},
+    network::{
+        move_networked_player_objs, AimMode, LocalPlayerID, MatchState, PlayerHead, PlayerID,
+        SharedAnchor, LOBBY_GRAMMAR,
+    },
     speech::{
-        check_fingers_close, fetch_recogniser, RecognizedWord, RecordingStatus, SpeechRecognizer,
+        check_fingers_close, fetch_recogniser, RecognizedWord, RecordingStatus, SpeechPlugin,
+        SpeechRecognizer,
     },
+    spell_registry::SpellRegistry,
     spells::{
         spawn_spell, spawn_spell_indicator, spawn_trajectory_indicator, SpellIndicator, SpellObj,
         TrajectoryIndicator,
     },
-    WizGgrsConfig,
+    PlayerInput, WizGgrsConfig,
 };
 
-pub struct SpellControlPlugin;
+// Roughly where the shoulders are from the eyes, with the head facing straight ahead.
+const SHOULDERS_OFFSET: Vec3 = Vec3::new(0.0, -0.25, 0.1);
+// Palms facing each other point nowhere in particular, so aiming with them falls back to the head.
+const MIN_PALMS_AIM: f32 = 0.5;
 
-#[derive(Copy, Clone)]
-pub enum Spell {
-    // don't use 0! it's used to represent no spell in the player inputs
-    Fireball = 1,
-    Lightning = 2,
-    Parry = 3,
-    Bomb = 4,
-    Wall = 5,
-    MagicMissile = 6,
-}
-
-#[derive(Debug)]
-pub struct SpellConvError;
-impl TryFrom<u32> for Spell {
-    type Error = SpellConvError;
-    fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            1 => Ok(Spell::Fireball),
-            2 => Ok(Spell::Lightning),
-            3 => Ok(Spell::Parry),
-            4 => Ok(Spell::Bomb),
-            5 => Ok(Spell::Wall),
-            6 => Ok(Spell::MagicMissile),
-            _ => Err(SpellConvError),
-        }
-    }
-}
+pub struct SpellControlPlugin;
 
 #[derive(States, Debug, Hash, Eq, PartialEq, Clone, Default)]
 pub enum SpellStatus {
@@ -57,21 +41,35 @@ pub enum SpellStatus {
 #[derive(Resource)]
 pub struct SpellSpawnLocation(pub Vec3);
 
+// Spells are kept by their wire id from here on.
 #[derive(Resource)]
-pub struct SelectedSpell(pub Option<Spell>);
+pub struct SelectedSpell(pub Option<u32>);
 
 #[derive(Resource, Clone)]
-pub struct QueuedSpell(pub Option<Spell>);
+pub struct QueuedSpell(pub Option<u32>);
 
-const SPELL_GRAMMAR: [&str; 6] = ["fireball", "lightning", "wind", "fire", "earth", "ice"];
+// How charged the armed spell is, from 0 to 1. It's sent along with the spell when it's cast.
+#[derive(Resource, Default)]
+pub struct SpellCharge(pub f32);
 
 impl Plugin for SpellControlPlugin {
     fn build(&self, app: &mut App) {
+        let registry = SpellRegistry::load();
+        // Without speech there's nothing to recognise, and loading the model would wait for it forever
+        if app.is_plugin_added::<SpeechPlugin>() {
+            // The lobby's commands go through the same recogniser
+            let grammar: Vec<&str> = registry.words().chain(LOBBY_GRAMMAR).collect();
+            app.insert_resource(SpeechRecognizer(fetch_recogniser(&grammar)));
+        }
         app.init_state::<SpellStatus>()
-            .insert_resource(SpeechRecognizer(fetch_recogniser(&SPELL_GRAMMAR)))
+            .insert_resource(registry)
             .insert_resource(SelectedSpell(None))
             .insert_resource(QueuedSpell(None))
-            .add_systems(OnEnter(RecordingStatus::Success), select_spell)
+            .init_resource::<SpellCharge>()
+            .add_systems(
+                OnEnter(RecordingStatus::Success),
+                select_spell.run_if(resource_equals(MatchState::Running)),
+            )
             .insert_resource(SpellSpawnLocation(Vec3 {
                 x: 0.0,
                 y: 0.0,
@@ -84,19 +82,28 @@ impl Plugin for SpellControlPlugin {
             )
             .add_systems(
                 Update,
-                check_spell_fire_input.run_if(in_state(SpellStatus::Armed)),
+                (charge_spell, check_spell_fire_input).run_if(in_state(SpellStatus::Armed)),
             )
             .add_systems(
                 Update,
-                check_if_done_firing.run_if(in_state(SpellStatus::Fire)),
+                // Spectators have no local player to cast spells with
+                check_if_done_firing
+                    .run_if(in_state(SpellStatus::Fire).and_then(resource_exists::<LocalPlayerID>)),
             )
+            .add_systems(OnEnter(SpellStatus::Armed), reset_spell_charge)
             .add_systems(OnEnter(SpellStatus::Armed), spawn_spell_indicator)
             .add_systems(OnEnter(SpellStatus::Armed), spawn_trajectory_indicator)
             .add_systems(OnExit(SpellStatus::Armed), despawn_spell_indicator)
             .add_systems(OnExit(SpellStatus::Armed), despawn_trajectory_indictaor)
             .add_systems(OnEnter(SpellStatus::Fire), queue_new_spell)
             .add_systems(OnExit(SpellStatus::Fire), despawn_trajectory_indictaor)
-            .add_systems(GgrsSchedule, spawn_new_spell_entities);
+            .add_systems(
+                GgrsSchedule,
+                spawn_new_spell_entities
+                    .after(move_networked_player_objs)
+                    .after(regen_mana)
+                    .run_if(resource_equals(MatchState::Running)),
+            );
     }
 }
 
@@ -105,11 +112,32 @@ fn check_spell_fire_input(
     hands_resource: Res<HandsResource>,
     mut next_spell_state: ResMut<NextState<SpellStatus>>,
 ) {
-    if !check_fingers_close(hand_bones, &hands_resource) {
+    if !check_fingers_close(&hand_bones, &hands_resource) {
         next_spell_state.set(SpellStatus::Fire)
     }
 }
 
+fn reset_spell_charge(mut charge: ResMut<SpellCharge>) {
+    charge.0 = 0.0;
+}
+
+// The longer a spell's held armed, the stronger it goes off, up to its full charge.
+fn charge_spell(
+    time: Res<Time>,
+    registry: Res<SpellRegistry>,
+    selected_spell: Res<SelectedSpell>,
+    mut charge: ResMut<SpellCharge>,
+) {
+    let Some(full) = selected_spell
+        .0
+        .and_then(|id| registry.get(id))
+        .and_then(|s| s.charge)
+    else {
+        return;
+    };
+    charge.0 = (charge.0 + time.delta_seconds() / full.secs).min(1.0);
+}
+
 fn despawn_spell_indicator(mut commands: Commands, spell_ind: Query<Entity, With<SpellIndicator>>) {
     if let Ok(indicator) = spell_ind.get_single() {
         commands.entity(indicator).despawn_recursive();
@@ -148,46 +176,96 @@ fn check_if_done_firing(
     }
 }
 
-fn spawn_new_spell_entities(
+// Which way both palms are facing, or None if they're facing each other and so nowhere in particular.
+pub fn palms_facing(left: Quat, right: Quat) -> Option<Vec3> {
+    // Palms face down their -Y
+    let palms = left * Vec3::NEG_Y + right * Vec3::NEG_Y;
+    (palms.length() >= MIN_PALMS_AIM).then(|| palms.normalize())
+}
+
+// Where a player's spell appears and which way it's aimed, worked out from nothing but their own input and
+// the rolled back targets, so every peer spawns it in the same place. Spells leave from between the palms.
+// The input's relative to the anchor, the targets and what comes back are in the world.
+pub fn aim_spell(
+    input: &PlayerInput,
+    anchor: &Transform,
+    targets: impl Iterator<Item = Vec3>,
+    assist_degrees: f32,
+) -> Transform {
+    let origin = input.left_hand_pos().lerp(input.right_hand_pos(), 0.5);
+    let gaze = input.head_rot() * Vec3::NEG_Z;
+    let aim = match input.aim_mode() {
+        AimMode::Head => gaze,
+        AimMode::Palms => {
+            palms_facing(input.left_hand_rot(), input.right_hand_rot()).unwrap_or(gaze)
+        }
+        AimMode::Shoulder => {
+            // Only the way the head's turned, looking up or down doesn't move the shoulders
+            let (yaw, _, _) = input.head_rot().to_euler(EulerRot::YXZ);
+            let shoulders = input.head_pos() + Quat::from_rotation_y(yaw) * SHOULDERS_OFFSET;
+            (origin - shoulders).try_normalize().unwrap_or(gaze)
+        }
+    };
+
+    // Goes for whichever target is closest to where they're aiming, if any is close enough
+    let cone = assist_degrees.to_radians();
+    let to_anchor = anchor.compute_affine().inverse();
+    let aim = targets
+        .filter_map(|target| (to_anchor.transform_point3(target) - origin).try_normalize())
+        .map(|to_target| (aim.angle_between(to_target), to_target))
+        .filter(|(angle, _)| *angle <= cone)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map_or(aim, |(_, to_target)| to_target);
+    *anchor
+        * Transform::from_translation(origin)
+            .with_rotation(Quat::from_rotation_arc(Vec3::NEG_Z, aim))
+}
+
+pub(crate) fn spawn_new_spell_entities(
     inputs: Res<PlayerInputs<WizGgrsConfig>>,
     mut commands: Commands,
-    player_objs: Query<&PlayerID, With<PlayerHead>>,
-    spawn_location: Res<SpellSpawnLocation>,
+    mut player_objs: Query<(&PlayerID, &mut Mana), With<PlayerHead>>,
+    bosses: Query<&Transform, With<Boss>>,
+    registry: Res<SpellRegistry>,
+    anchor: Res<SharedAnchor>,
 ) {
-    for p in player_objs.iter() {
+    for (p, mut mana) in player_objs.iter_mut() {
         let input = inputs[p.handle].0;
-
-        let head_transform = Transform::from_translation(input.head_pos.lerp(input.head_pos, 0.5))
-            .with_rotation(input.head_rot);
-
-        if input.spell != 0 {
-            spawn_spell(
-                &mut commands,
-                input,
-                p.handle,
-                spawn_location.0,
-                head_transform,
-            );
+        if input.spell() == 0 {
+            continue;
+        }
+        // Peers check they have the same spells before playing together, so this is only a broken recording
+        let Some(spell) = registry.get(input.spell()) else {
+            warn!("player {} cast unknown spell {}", p.handle, input.spell());
+            continue;
+        };
+        // Paid for here rather than when the spell's armed, so every peer agrees on who could afford what
+        if mana.try_cast(spell).is_err() {
+            continue;
         }
+
+        let origin = aim_spell(
+            &input,
+            &anchor.0,
+            bosses.iter().map(|t| t.translation),
+            spell.aim_assist_degrees,
+        );
+        spawn_spell(&mut commands, spell, p.handle, origin, input.charge());
     }
 }
 
 fn select_spell(
     word: Res<RecognizedWord>,
+    registry: Res<SpellRegistry>,
     mut next_spell_state: ResMut<NextState<SpellStatus>>,
     mut selected_spell: ResMut<SelectedSpell>,
 ) {
-    let (next_s, s_spell) = match &word.0[..] {
-        "fireball" => (SpellStatus::Armed, Some(Spell::Fireball)),
-        "lightning" => (SpellStatus::Armed, Some(Spell::Lightning)),
-        "wind" => (SpellStatus::Armed, Some(Spell::Parry)),
-        "fire" => (SpellStatus::Armed, Some(Spell::Bomb)),
-        "earth" => (SpellStatus::Armed, Some(Spell::Wall)),
-        "ice" => (SpellStatus::Armed, Some(Spell::MagicMissile)),
-        _ => (SpellStatus::None, None),
-    };
-    next_spell_state.set(next_s);
-    selected_spell.0 = s_spell;
+    let spell = registry.by_word(&word.0);
+    next_spell_state.set(match spell {
+        Some(_) => SpellStatus::Armed,
+        None => SpellStatus::None,
+    });
+    selected_spell.0 = spell.map(|s| s.id);
 }
 
 fn palm_mid_point_track(
@@ -0,0 +1,243 @@
+use std::{
+    fmt::{self, Display},
+    hash::Hasher,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::{MatName, MeshName},
+    input::MAX_SPELL_ID,
+    network::{StableHasher, LOBBY_GRAMMAR},
+    projectile::{Damage, ProjectileParams},
+};
+
+// Read at startup, so spells can be added or tuned without rebuilding.
+#[cfg(not(target_os = "android"))]
+const SPELLS_PATH: &str = "assets/spells.ron";
+// There's no assets folder to edit on the headset, so look next to the config file
+#[cfg(target_os = "android")]
+const SPELLS_PATH: &str =
+    "/storage/emulated/0/Android/data/com.github.goudanough.wizards/files/spells.ron";
+// Used when there's no spells file, or it's broken.
+const BUILTIN_SPELLS: &str = include_str!("../assets/spells.ron");
+
+// Everything about one spell. Players cast it by saying any of its words.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpellDef {
+    pub name: String,
+    pub words: Vec<String>,
+    // What the spell is sent as in `PlayerInput`, 0 is no spell
+    pub id: u32,
+    // Held between the palms while the spell is armed
+    pub indicator: Indicator,
+    #[serde(default)]
+    pub trajectory: TrajectoryKind,
+    // Spells aimed within this many degrees of the boss go straight for it
+    #[serde(default)]
+    pub aim_assist_degrees: f32,
+    pub effect: SpellEffect,
+    // Spells without it go off the same however long they're held
+    #[serde(default)]
+    pub charge: Option<Charge>,
+    // Taken from the caster's mana, the spell doesn't go off if they haven't got enough
+    #[serde(default)]
+    pub mana_cost: f32,
+    // How long after casting it before it can be cast again
+    #[serde(default)]
+    pub cooldown_secs: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Indicator {
+    pub mesh: MeshName,
+    pub material: MatName,
+}
+
+// How much stronger a spell gets for being held armed, each multiplier is how much bigger it is at full charge.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Charge {
+    // How long it takes to fully charge
+    pub secs: f32,
+    pub scale: f32,
+    pub speed: f32,
+    pub damage: f32,
+}
+
+impl Charge {
+    // `level` goes from 0 for uncharged, which is the spell as written, to 1.
+    pub fn projectile(&self, params: ProjectileParams, level: f32) -> ProjectileParams {
+        ProjectileParams {
+            scale: params.scale * self.scale(level),
+            speed: params.speed * lerp_multiplier(self.speed, level),
+            damage: Damage {
+                amount: params.damage.amount * lerp_multiplier(self.damage, level),
+                ..params.damage
+            },
+            ..params
+        }
+    }
+
+    // How much bigger than normal the spell is, which the indicator shows while charging.
+    pub fn scale(&self, level: f32) -> f32 {
+        lerp_multiplier(self.scale, level)
+    }
+}
+
+fn lerp_multiplier(full: f32, level: f32) -> f32 {
+    1.0 + (full - 1.0) * level.clamp(0.0, 1.0)
+}
+
+// What's shown while aiming an armed spell.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrajectoryKind {
+    #[default]
+    None,
+    // A line straight out from the palms to whatever it'd hit
+    StraightLaser,
+}
+
+// What casting the spell does. Each kind has its own handler in `spells`, the numbers are up to the spell.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum SpellEffect {
+    // Flies straight ahead from between the palms
+    Projectile(ProjectileParams),
+    // Hits whatever's straight ahead straight away
+    Missile {
+        range: f32,
+        damage: Damage,
+    },
+    // Shields on both palms that knock projectiles away
+    Parry {
+        secs: f32,
+    },
+    // Thrown by touching it, goes off once the fuse runs out
+    Bomb {
+        fuse_secs: f32,
+        radius: f32,
+        damage: Damage,
+    },
+    // Built along the caster's path while they walk
+    Wall {
+        build_secs: f32,
+        stand_secs: f32,
+    },
+}
+
+#[derive(Debug)]
+pub enum SpellRegistryError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl Display for SpellRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpellRegistryError::Io { path, error } => {
+                write!(f, "couldn't read spells file {}: {error}", path.display())
+            }
+            SpellRegistryError::Parse(error) => write!(f, "invalid spells file: {error}"),
+            SpellRegistryError::Invalid(reason) => write!(f, "invalid spells: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SpellRegistryError {}
+
+// Every spell that can be cast. Peers have to agree on it, or the same input would cast different things.
+#[derive(Resource, Debug, Clone)]
+pub struct SpellRegistry {
+    spells: Vec<SpellDef>,
+}
+
+impl SpellRegistry {
+    pub fn load() -> Self {
+        let path = Path::new(SPELLS_PATH);
+        if path.exists() {
+            match Self::read_file(path) {
+                Ok(registry) => return registry,
+                Err(e) => error!("{e}, using the built-in spells instead"),
+            }
+        }
+        Self::from_ron(BUILTIN_SPELLS).expect("the built-in spells are valid")
+    }
+
+    fn read_file(path: &Path) -> Result<Self, SpellRegistryError> {
+        let text = std::fs::read_to_string(path).map_err(|error| SpellRegistryError::Io {
+            path: path.to_owned(),
+            error,
+        })?;
+        Self::from_ron(&text)
+    }
+
+    fn from_ron(text: &str) -> Result<Self, SpellRegistryError> {
+        let spells: Vec<SpellDef> = ron::from_str(text).map_err(SpellRegistryError::Parse)?;
+        let invalid = |reason: String| Err(SpellRegistryError::Invalid(reason));
+
+        let mut words: Vec<&str> = Vec::new();
+        for (i, spell) in spells.iter().enumerate() {
+            if !(1..=MAX_SPELL_ID).contains(&spell.id) {
+                return invalid(format!(
+                    "{}'s id {} isn't between 1 and {MAX_SPELL_ID}",
+                    spell.name, spell.id
+                ));
+            }
+            if let Some(other) = spells[..i].iter().find(|s| s.id == spell.id) {
+                return invalid(format!(
+                    "{} and {} both have id {}",
+                    other.name, spell.name, spell.id
+                ));
+            }
+            if spell.mana_cost < 0.0 {
+                return invalid(format!("{} can't cost less than 0 mana", spell.name));
+            }
+            if spell.cooldown_secs < 0.0 {
+                return invalid(format!("{}'s cooldown can't be less than 0s", spell.name));
+            }
+            if spell.charge.is_some_and(|c| c.secs <= 0.0) {
+                return invalid(format!("{} has to charge for longer than 0s", spell.name));
+            }
+            if spell.words.is_empty() {
+                return invalid(format!("{} has no words to cast it with", spell.name));
+            }
+            for word in spell.words.iter() {
+                // The lobby listens with the same recogniser
+                if words.contains(&word.as_str()) || LOBBY_GRAMMAR.contains(&word.as_str()) {
+                    return invalid(format!("\"{word}\" is used more than once"));
+                }
+                words.push(word);
+            }
+        }
+        Ok(Self { spells })
+    }
+
+    pub fn get(&self, id: u32) -> Option<&SpellDef> {
+        self.spells.iter().find(|s| s.id == id)
+    }
+
+    pub fn by_word(&self, word: &str) -> Option<&SpellDef> {
+        self.spells
+            .iter()
+            .find(|s| s.words.iter().any(|w| w == word))
+    }
+
+    // Every word that casts a spell, for the speech recogniser.
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.spells
+            .iter()
+            .flat_map(|s| s.words.iter().map(String::as_str))
+    }
+
+    // Compared between peers, and against recordings, to make sure everyone has the same spells.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write(&bincode::serialize(&self.spells).expect("spells always serialize"));
+        hasher.finish()
+    }
+}
@@ -2,22 +2,33 @@ use std::time::Duration;
 
 use ::bevy::prelude::*;
 use bevy::math::primitives;
-use bevy_ggrs::{AddRollbackCommandExtension, GgrsSchedule, PlayerInputs, Rollback};
+use bevy_ggrs::{AddRollbackCommandExtension, GgrsApp, GgrsSchedule, PlayerInputs, Rollback};
 use bevy_hanabi::{ParticleEffect, ParticleEffectBundle};
-use bevy_oxr::xr_input::trackers::{OpenXRLeftEye, OpenXRRightEye};
 use bevy_xpbd_3d::prelude::*;
 
 use crate::assets::{AssetHandles, EffectName, MatName, MeshName};
-use crate::boss::BossHealth;
-use crate::network::{move_networked_player_objs, PlayerID, PlayerLeftPalm, PlayerRightPalm};
+use crate::boss::{Boss, BossHealth};
+use crate::duel::{
+    blast_hits_head, damage_heads, players_can_be_hurt, ray_hits_head, PlayerHealth, RoundState,
+};
+use crate::network::{
+    hash_floats, hash_timer, move_networked_player_objs, LastLocalInput, PlayerHead, PlayerID,
+    PlayerLeftPalm, PlayerRightPalm, RollbackChecksumApp, SharedAnchor,
+};
 use crate::projectile::{
-    spawn_projectile, update_linear_movement, DamageHit, DamageMask, Projectile,
-    ProjectileHitEffect, ProjectileType,
+    spawn_projectile, update_linear_movement, Caster, Projectile, ProjectileHitEffect,
+    ProjectileType,
 };
-use crate::spell_control::{SelectedSpell, Spell, SpellSpawnLocation};
-use crate::{PhysLayer, PlayerInput, WizGgrsConfig};
+use crate::spell_control::{
+    aim_spell, spawn_new_spell_entities, SelectedSpell, SpellCharge, SpellSpawnLocation,
+};
+use crate::spell_registry::{SpellDef, SpellEffect, SpellRegistry, TrajectoryKind};
+use crate::{PhysLayer, WizGgrsConfig};
 pub struct SpellsPlugin;
 
+// Size of the armed spell's indicator, before it's charged.
+const INDICATOR_SCALE: f32 = 0.2;
+
 #[derive(Component)]
 pub struct SpellIndicator;
 
@@ -29,192 +40,178 @@ pub struct TrajectoryIndicator {
 #[derive(Component)]
 pub struct StraightLaserTrajInd;
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub struct SpellObj;
 
-#[derive(Component)]
-pub struct MissileSpell;
+// How charged a spell was when it was cast, from 0 to 1.
+#[derive(Component, Clone, Copy)]
+pub struct Charged(f32);
 
-#[derive(Component)]
-pub struct FireSpell;
+// Each kind of spell is marked with the wire id of the spell cast, so its handler can look it up.
+#[derive(Component, Clone, Copy)]
+pub struct MissileSpell(u32);
 
-#[derive(Component)]
-pub struct LightningSpell;
+#[derive(Component, Clone, Copy)]
+pub struct ProjectileSpell(u32);
 
-#[derive(Component)]
-pub struct ParrySpell;
+#[derive(Component, Clone, Copy)]
+pub struct ParrySpell(u32);
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub struct ParryObj;
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub struct ParriedProjectile;
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 pub struct ParryTimer(Timer);
 
-#[derive(Component)]
-pub struct BombSpell;
+#[derive(Component, Clone, Copy)]
+pub struct BombSpell(u32);
 
-#[derive(Component)]
-pub struct BombObj;
+// Keeps the spell's id around for when it goes off.
+#[derive(Component, Clone, Copy)]
+pub struct BombObj(u32);
 
 #[derive(Component)]
 pub struct BombExplosionEffect;
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub struct HandObj;
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 pub struct BombTimer(Timer);
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 pub struct DespawnTimer(Timer);
 
-#[derive(Component)]
-pub struct WallSpell;
+#[derive(Component, Clone, Copy)]
+pub struct WallSpell(u32);
 
 // Component for handling the lifetime of a wall.
-#[derive(Component)]
+#[derive(Component, Clone, Debug)]
 struct Wall {
     previous_point: Vec3,
     building: bool,
     timer: Timer,
+    // How long it stands once it's built
+    stand_secs: f32,
 }
 impl Plugin for SpellsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            GgrsSchedule,
-            (
-                handle_lightning,
-                handle_fireballs,
-                handle_missiles,
-                init_walls,
-                handle_walls,
-                handle_parry,
-                parry_check,
-                handle_bomb,
-                handle_bomb_explode,
-                hand_bomb_collision,
-                despawn_timed_entities,
+        app.rollback_component_with_copy::<SpellObj>()
+            .rollback_component_with_copy::<Charged>()
+            .checksum_rollback_component::<Charged>(|c| hash_floats(&[c.0]))
+            .rollback_component_with_copy::<MissileSpell>()
+            .rollback_component_with_copy::<ProjectileSpell>()
+            .rollback_component_with_copy::<ParrySpell>()
+            .rollback_component_with_copy::<ParryObj>()
+            .rollback_component_with_copy::<ParriedProjectile>()
+            .rollback_component_with_copy::<BombSpell>()
+            .rollback_component_with_copy::<BombObj>()
+            .rollback_component_with_copy::<HandObj>()
+            .rollback_component_with_copy::<WallSpell>()
+            .rollback_component_with_clone::<ParryTimer>()
+            .checksum_rollback_component::<ParryTimer>(|t| hash_timer(&t.0))
+            .rollback_component_with_clone::<BombTimer>()
+            .checksum_rollback_component::<BombTimer>(|t| hash_timer(&t.0))
+            .rollback_component_with_clone::<DespawnTimer>()
+            .checksum_rollback_component::<DespawnTimer>(|t| hash_timer(&t.0))
+            .rollback_component_with_clone::<Wall>()
+            .checksum_rollback_component::<Wall>(|w| {
+                let p = w.previous_point;
+                hash_floats(&[p.x, p.y, p.z, w.building as u8 as f32, w.stand_secs])
+                    ^ hash_timer(&w.timer)
+            })
+            .rollback_component_with_clone::<ExternalForce>()
+            .checksum_rollback_component::<ExternalForce>(|f| {
+                let (force, torque) = (f.force(), f.torque());
+                hash_floats(&[force.x, force.y, force.z, torque.x, torque.y, torque.z])
+            })
+            .add_systems(
+                GgrsSchedule,
+                (
+                    handle_projectile_spells,
+                    handle_missiles,
+                    init_walls,
+                    handle_walls,
+                    handle_parry,
+                    parry_check,
+                    handle_bomb,
+                    handle_bomb_explode,
+                    hand_bomb_collision,
+                    despawn_timed_entities,
+                )
+                    .chain()
+                    .before(update_linear_movement)
+                    .after(move_networked_player_objs)
+                    // Spells are handled on the frame they're cast
+                    .after(spawn_new_spell_entities),
             )
-                .chain()
-                .before(update_linear_movement)
-                .after(move_networked_player_objs),
-        )
-        .add_systems(
-            Update,
-            (handle_straight_laser_traj_ind, track_spell_indicator),
-        );
+            .add_systems(
+                Update,
+                (handle_straight_laser_traj_ind, track_spell_indicator),
+            );
     }
 }
 
 pub fn spawn_spell(
     commands: &mut Commands,
-    input: PlayerInput,
+    spell: &SpellDef,
     p_id: usize,
-    palm_mid_point: Vec3,
-    head_transform: Transform,
+    origin: Transform,
+    charge: f32,
 ) {
-    let spell = input.spell.try_into().unwrap();
-
-    match spell {
-        Spell::Fireball => commands
-            .spawn((
-                SpellObj,
-                FireSpell,
-                PlayerID { handle: p_id },
-                SpatialBundle {
-                    transform: Transform::from_translation(palm_mid_point)
-                        .with_rotation(head_transform.rotation), // TODO currently incorrect direction, needs integrating with a proper aiming system
-                    ..Default::default()
-                },
-            ))
-            .add_rollback(),
-        Spell::Lightning => commands
-            .spawn((
-                SpellObj,
-                LightningSpell,
-                PlayerID { handle: p_id },
-                SpatialBundle {
-                    transform: Transform::from_translation(palm_mid_point)
-                        .with_rotation(head_transform.rotation), // TODO currently incorrect direction, needs integrating with a proper aiming system
-                    ..Default::default()
-                },
-            ))
-            .add_rollback(),
-        Spell::Parry => commands
-            .spawn((
-                SpellObj,
-                ParrySpell,
-                PlayerID { handle: p_id },
-                SpatialBundle {
-                    transform: Transform::from_translation(palm_mid_point)
-                        .with_rotation(head_transform.rotation),
-                    ..Default::default()
-                },
-            ))
-            .add_rollback(),
-        Spell::Bomb => commands
-            .spawn((
-                SpellObj,
-                BombSpell,
-                PlayerID { handle: p_id },
-                SpatialBundle {
-                    transform: Transform::from_translation(palm_mid_point)
-                        .with_rotation(head_transform.rotation), // TODO currently incorrect direction, needs integrating with a proper aiming system
-                    ..Default::default()
-                },
-            ))
-            .add_rollback(),
-        Spell::Wall => commands
-            .spawn((SpellObj, WallSpell, PlayerID { handle: p_id }))
-            .add_rollback(),
-        Spell::MagicMissile => commands
-            .spawn((
-                SpellObj,
-                MissileSpell,
-                PlayerID { handle: p_id },
-                SpatialBundle {
-                    transform: Transform::from_translation(palm_mid_point)
-                        .with_rotation(head_transform.rotation), // TODO currently incorrect direction, needs integrating with a proper aiming system
-                    ..Default::default()
-                },
-            ))
-            .add_rollback(),
+    let mut spell_obj = commands.spawn((
+        SpellObj,
+        Charged(charge),
+        PlayerID { handle: p_id },
+        SpatialBundle {
+            transform: origin,
+            ..Default::default()
+        },
+    ));
+    match spell.effect {
+        SpellEffect::Projectile(_) => spell_obj.insert(ProjectileSpell(spell.id)),
+        SpellEffect::Missile { .. } => spell_obj.insert(MissileSpell(spell.id)),
+        SpellEffect::Parry { .. } => spell_obj.insert(ParrySpell(spell.id)),
+        SpellEffect::Bomb { .. } => spell_obj.insert(BombSpell(spell.id)),
+        SpellEffect::Wall { .. } => spell_obj.insert(WallSpell(spell.id)),
     };
+    spell_obj.add_rollback();
 }
 
-fn handle_fireballs(
+pub(crate) fn handle_projectile_spells(
     mut commands: Commands,
-    spell_objs: Query<(&Transform, Entity), With<FireSpell>>,
+    spell_objs: Query<(&Transform, Entity, &PlayerID, &ProjectileSpell, &Charged)>,
+    registry: Res<SpellRegistry>,
     asset_handles: Res<AssetHandles>,
 ) {
-    for (t, e) in spell_objs.iter() {
-        spawn_projectile(&mut commands, ProjectileType::Fireball, t, &asset_handles);
+    for (t, e, p_id, spell, charged) in spell_objs.iter() {
         commands.entity(e).despawn_recursive();
-    }
-}
-
-fn handle_lightning(
-    mut commands: Commands,
-    spell_objs: Query<(&Transform, Entity), With<LightningSpell>>,
-    asset_handles: Res<AssetHandles>,
-) {
-    for (t, e) in spell_objs.iter() {
-        spawn_projectile(
+        let Some(spell) = registry.get(spell.0) else {
+            continue;
+        };
+        let SpellEffect::Projectile(params) = spell.effect else {
+            continue;
+        };
+        let params = spell
+            .charge
+            .map_or(params, |c| c.projectile(params, charged.0));
+        let projectile = spawn_projectile(
             &mut commands,
-            ProjectileType::LightningBolt,
+            ProjectileType::Spell(params),
             t,
             &asset_handles,
         );
-        commands.entity(e).despawn_recursive();
+        commands.entity(projectile).insert(Caster(p_id.handle));
     }
 }
 
 fn handle_bomb(
     mut commands: Commands,
-    spell_objs: Query<(&Transform, Entity, &PlayerID), With<BombSpell>>,
+    spell_objs: Query<(&Transform, Entity, &PlayerID, &BombSpell)>,
+    registry: Res<SpellRegistry>,
     asset_handles: Res<AssetHandles>,
     mut player_left_palms: Query<
         (Entity, &PlayerID),
@@ -233,7 +230,13 @@ fn handle_bomb(
         ),
     >,
 ) {
-    for (t, e, id) in spell_objs.iter() {
+    for (t, e, id, spell) in spell_objs.iter() {
+        commands.entity(e).despawn();
+        let Some(SpellEffect::Bomb { fuse_secs, .. }) = registry.get(spell.0).map(|s| s.effect)
+        else {
+            continue;
+        };
+        let fuse = Duration::from_secs_f32(fuse_secs);
         commands
             .spawn((
                 PbrBundle {
@@ -243,10 +246,10 @@ fn handle_bomb(
                         .with_scale(0.5 * Vec3::ONE),
                     ..Default::default()
                 },
-                BombObj,
+                BombObj(spell.0),
                 PlayerID { handle: id.handle },
                 CollisionLayers::new(PhysLayer::Bomb, LayerMask::ALL ^ PhysLayer::BossProjectile),
-                BombTimer(Timer::new(Duration::from_secs(5), TimerMode::Once)),
+                BombTimer(Timer::new(fuse, TimerMode::Once)),
                 Collider::sphere(0.1),
             ))
             .add_rollback();
@@ -261,7 +264,7 @@ fn handle_bomb(
                 //     ..default()
                 // },
                 HandObj,
-                BombTimer(Timer::new(Duration::from_secs(5), TimerMode::Once)),
+                BombTimer(Timer::new(fuse, TimerMode::Once)),
             ))
             .add_rollback()
             .id();
@@ -275,7 +278,7 @@ fn handle_bomb(
                 //     ..default()
                 // },
                 HandObj,
-                BombTimer(Timer::new(Duration::from_secs(5), TimerMode::Once)),
+                BombTimer(Timer::new(fuse, TimerMode::Once)),
             ))
             .add_rollback()
             .id();
@@ -303,8 +306,6 @@ fn handle_bomb(
                     .push_children(&[right_hand_effect]);
             }
         }
-
-        commands.entity(e).despawn();
     }
 }
 
@@ -356,23 +357,45 @@ fn hand_bomb_collision(
 fn handle_bomb_explode(
     mut commands: Commands,
     asset_handles: Res<AssetHandles>,
+    registry: Res<SpellRegistry>,
     time: Res<Time>,
     hands_effect: Query<(Entity, &PlayerID), (With<HandObj>, Without<BombObj>)>,
-    mut bomb_objs_query: Query<(Entity, &Transform, &mut BombTimer, &PlayerID), With<BombObj>>,
+    mut bomb_objs_query: Query<(Entity, &Transform, &mut BombTimer, &PlayerID, &BombObj)>,
+    mut heads: Query<(&Transform, &PlayerID, &mut PlayerHealth), With<PlayerHead>>,
+    round: Option<Res<RoundState>>,
 ) {
-    for (bomb_e, bomb_trans, mut bomb_timer, id) in bomb_objs_query.iter_mut() {
+    let fighting = players_can_be_hurt(round);
+    let mut hits = Vec::new();
+    for (bomb_e, bomb_trans, mut bomb_timer, id, bomb) in bomb_objs_query.iter_mut() {
         if bomb_timer.0.tick(time.delta()).finished() {
             commands.entity(bomb_e).despawn();
+            let Some(SpellEffect::Bomb { radius, damage, .. }) =
+                registry.get(bomb.0).map(|s| s.effect)
+            else {
+                continue;
+            };
+            // The blast's collider only hurts the boss, so any heads it reaches are hit here, bar the bomber's
+            if fighting {
+                hits.extend(
+                    heads
+                        .iter()
+                        .filter(|(head, p, _)| {
+                            p.handle != id.handle
+                                && blast_hits_head(bomb_trans.translation, radius, head.translation)
+                        })
+                        .map(|(_, p, _)| (p.handle, damage.amount)),
+                );
+            }
             commands
                 .spawn((
                     Projectile,
-                    ProjectileHitEffect::Damage(DamageHit(DamageMask::FIRE, 25.0)),
+                    ProjectileHitEffect::Damage(damage.into()),
                     SpatialBundle {
                         transform: Transform::from_translation(bomb_trans.translation)
                             .with_rotation(bomb_trans.rotation),
                         ..default()
                     },
-                    Collider::sphere(1.0),
+                    Collider::sphere(radius),
                     CollisionLayers::new(
                         PhysLayer::PlayerProjectile,
                         (((LayerMask::ALL ^ PhysLayer::Player) ^ PhysLayer::BossProjectile)
@@ -402,16 +425,22 @@ fn handle_bomb_explode(
             }
         }
     }
+    damage_heads(hits, &mut heads);
 }
 
 fn handle_parry(
     mut commands: Commands,
     left_palms: Query<(Entity, &PlayerID), With<PlayerLeftPalm>>,
     right_palms: Query<(Entity, &PlayerID), With<PlayerRightPalm>>,
-    spell_objs: Query<(Entity, &PlayerID), With<ParrySpell>>,
+    spell_objs: Query<(Entity, &PlayerID, &ParrySpell)>,
+    registry: Res<SpellRegistry>,
     //asset_handles: Res<AssetHandles>,
 ) {
-    for (e, p) in spell_objs.iter() {
+    for (e, p, spell) in spell_objs.iter() {
+        commands.entity(e).despawn_recursive();
+        let Some(SpellEffect::Parry { secs }) = registry.get(spell.0).map(|s| s.effect) else {
+            continue;
+        };
         let parry_left = commands
             .spawn((
                 ParryObj,
@@ -428,7 +457,7 @@ fn handle_parry(
                     (LayerMask::ALL ^ PhysLayer::Player) ^ PhysLayer::PlayerProjectile,
                 ),
                 Collider::sphere(0.12),
-                ParryTimer(Timer::from_seconds(5.0, TimerMode::Once)),
+                ParryTimer(Timer::from_seconds(secs, TimerMode::Once)),
             ))
             .add_rollback()
             .id();
@@ -449,7 +478,7 @@ fn handle_parry(
                     (LayerMask::ALL ^ PhysLayer::Player) ^ PhysLayer::PlayerProjectile,
                 ),
                 Collider::sphere(0.12),
-                ParryTimer(Timer::from_seconds(5.0, TimerMode::Once)),
+                ParryTimer(Timer::from_seconds(secs, TimerMode::Once)),
             ))
             .add_rollback()
             .id();
@@ -471,8 +500,6 @@ fn handle_parry(
                     .push_children(&[parry_right]);
             }
         }
-
-        commands.entity(e).despawn_recursive();
     }
 }
 
@@ -537,31 +564,43 @@ fn parry_check(
 // Respond to wall spell cast by creating a wall entity.
 fn init_walls(
     mut commands: Commands,
-    spell_objs: Query<(Entity, &PlayerID), With<WallSpell>>,
+    spell_objs: Query<(Entity, &PlayerID, &WallSpell)>,
+    registry: Res<SpellRegistry>,
     inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    anchor: Res<SharedAnchor>,
 ) {
-    for (e, p_id) in spell_objs.iter() {
-        let input = inputs[p_id.handle];
-        let head_pos = input.0.head_pos;
-        commands.spawn((
-            SpatialBundle {
-                transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-                ..Default::default()
-            },
-            Wall {
-                // Initialise the wall at the players head position.
-                previous_point: Vec3::new(head_pos.x, head_pos.y / 2.0, head_pos.z),
-                building: true,
-                // Initial timer, for wall creation.
-                timer: Timer::from_seconds(3.0, TimerMode::Once),
-            },
-            // PlayerID so we know who's wall it is.
-            PlayerID {
-                handle: p_id.handle,
-            },
-        ));
+    for (e, p_id, spell) in spell_objs.iter() {
         // Despawn SpellObj,
         commands.entity(e).despawn();
+        let Some(SpellEffect::Wall {
+            build_secs,
+            stand_secs,
+        }) = registry.get(spell.0).map(|s| s.effect)
+        else {
+            continue;
+        };
+        let input = inputs[p_id.handle];
+        let head_pos = anchor.0.transform_point(input.0.head_pos());
+        commands
+            .spawn((
+                SpatialBundle {
+                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
+                    ..Default::default()
+                },
+                Wall {
+                    // Initialise the wall at the players head position.
+                    previous_point: Vec3::new(head_pos.x, head_pos.y / 2.0, head_pos.z),
+                    building: true,
+                    // Initial timer, for wall creation.
+                    timer: Timer::from_seconds(build_secs, TimerMode::Once),
+                    stand_secs,
+                },
+                // PlayerID so we know who's wall it is.
+                PlayerID {
+                    handle: p_id.handle,
+                },
+            ))
+            .add_rollback();
     }
 }
 
@@ -572,6 +611,7 @@ fn handle_walls(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     inputs: Res<PlayerInputs<WizGgrsConfig>>,
+    anchor: Res<SharedAnchor>,
     time: Res<Time>,
 ) {
     for (mut wall, p_id, e) in walls.iter_mut() {
@@ -580,11 +620,11 @@ fn handle_walls(
         // Indicate building is over, and start a new timer.
         if wall.timer.just_finished() && wall.building {
             wall.building = false;
-            wall.timer = Timer::from_seconds(10.0, TimerMode::Once);
+            wall.timer = Timer::from_seconds(wall.stand_secs, TimerMode::Once);
         }
         // If we're building, check if we've moved far enough to spawn a new segment, if we have then spawn a new segment, and update previous position.
         if wall.building {
-            let head_pos = inputs[p_id.handle].0.head_pos;
+            let head_pos = anchor.0.transform_point(inputs[p_id.handle].0.head_pos());
             let head_pos_flat = Vec3::new(head_pos.x, head_pos.y / 2.0, head_pos.z);
             if (head_pos_flat - wall.previous_point).length() >= 0.2 {
                 let id = commands
@@ -631,33 +671,61 @@ fn handle_walls(
 // Handle cast missile spells.
 fn handle_missiles(
     mut commands: Commands,
-    spell_objs: Query<(&Transform, Entity), With<MissileSpell>>,
+    spell_objs: Query<(&Transform, Entity, &PlayerID, &MissileSpell)>,
+    registry: Res<SpellRegistry>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut mats: ResMut<Assets<StandardMaterial>>,
     mut boss_health: Query<&mut BossHealth>,
+    mut heads: Query<(&Transform, &PlayerID, &mut PlayerHealth), With<PlayerHead>>,
+    round: Option<Res<RoundState>>,
     spatial_query: SpatialQuery,
 ) {
-    for (t, e) in spell_objs.iter() {
+    let fighting = players_can_be_hurt(round);
+    let mut hits = Vec::new();
+    for (t, e, caster, spell) in spell_objs.iter() {
+        // Despawn SpellObj, since the spell has been handled now.
+        commands.entity(e).despawn();
+        let Some(SpellEffect::Missile { range, damage }) = registry.get(spell.0).map(|s| s.effect)
+        else {
+            continue;
+        };
         // Spell is hitscan, so raycast to find what the spell hits.
-        let mut beam_length = 50.0;
-        if let Some(target) = spatial_query.cast_ray(
+        let mut beam_length = range;
+        let target = spatial_query.cast_ray(
             t.translation,
             t.forward(),
             beam_length,
             true,
             SpatialQueryFilter::from_mask([PhysLayer::Terrain, PhysLayer::Boss]),
-        ) {
+        );
+        if let Some(target) = target {
+            beam_length = target.time_of_impact;
+        }
+        // The closest other player's head in the way takes the hit instead, lowest handle if they're level
+        let head_hit = heads
+            .iter()
+            .filter(|(_, p, _)| fighting && p.handle != caster.handle)
+            .filter_map(|(head, p, _)| {
+                ray_hits_head(
+                    t.translation,
+                    t.forward().into(),
+                    beam_length,
+                    head.translation,
+                )
+                .map(|along| (along, p.handle))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        if let Some((along, handle)) = head_hit {
+            hits.push((handle, damage.amount));
+            beam_length = along;
+        } else if let Some(target) = target {
             // If we've hit the boss, damage it.
             if let Ok(mut health) = boss_health.get_mut(target.entity) {
-                // TODO change this to use the damage type we actually want to use for this.
-                if health.damage_mask.intersect(&DamageMask::FIRE) {
-                    health.current -= 25.0;
+                if health.damage_mask.intersect(&damage.kind.into()) {
+                    health.current -= damage.amount;
                 }
             }
-            beam_length = target.time_of_impact;
-        };
-        // Despawn SpellObj, since the spell has been handled now.
-        commands.entity(e).despawn();
+        }
 
         // If the spell hits anything, spawn a visual to represent this.
         let beam_start = t.translation;
@@ -679,109 +747,43 @@ fn handle_missiles(
             ))
             .add_rollback();
     }
+    damage_heads(hits, &mut heads);
 }
 
 pub fn spawn_spell_indicator(
     mut commands: Commands,
     asset_handles: Res<AssetHandles>,
+    registry: Res<SpellRegistry>,
     selected_spell: Res<SelectedSpell>,
     palm_mid_point: Res<SpellSpawnLocation>,
 ) {
-    match selected_spell.0.unwrap() {
-        Spell::Fireball => commands.spawn((
-            SpellIndicator,
-            PbrBundle {
-                mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                material: asset_handles.mats[MatName::Red as usize].clone(),
-                transform: Transform::from_translation(palm_mid_point.0)
-                    .with_scale(0.2 * Vec3::ONE),
-                ..Default::default()
-            },
-        )),
-        Spell::Lightning => commands.spawn((
-            SpellIndicator,
-            PbrBundle {
-                mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                material: asset_handles.mats[MatName::Blue as usize].clone(),
-                transform: Transform::from_translation(palm_mid_point.0)
-                    .with_scale(0.2 * Vec3::ONE),
-                ..Default::default()
-            },
-        )),
-        Spell::Parry => commands.spawn((
-            SpellIndicator,
-            PbrBundle {
-                mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                material: asset_handles.mats[MatName::Purple as usize].clone(),
-                transform: Transform::from_translation(palm_mid_point.0)
-                    .with_scale(0.2 * Vec3::ONE),
-                ..Default::default()
-            },
-        )),
-        Spell::Bomb => commands.spawn((
-            SpellIndicator,
-            PbrBundle {
-                mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                material: asset_handles.mats[MatName::Green as usize].clone(),
-                transform: Transform::from_translation(palm_mid_point.0)
-                    .with_scale(0.2 * Vec3::ONE),
-                ..Default::default()
-            },
-        )),
-        Spell::Wall => commands.spawn((
-            SpellIndicator,
-            PbrBundle {
-                mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                material: asset_handles.mats[MatName::Blue as usize].clone(),
-                transform: Transform::from_translation(palm_mid_point.0)
-                    .with_scale(0.2 * Vec3::ONE),
-                ..Default::default()
-            },
-        )),
-        Spell::MagicMissile => commands.spawn((
-            SpellIndicator,
-            PbrBundle {
-                mesh: asset_handles.meshes[MeshName::Sphere as usize].clone(),
-                material: asset_handles.mats[MatName::Red as usize].clone(),
-                transform: Transform::from_translation(palm_mid_point.0)
-                    .with_scale(0.2 * Vec3::ONE),
-                ..Default::default()
-            },
-        )),
+    let Some(spell) = selected_spell.0.and_then(|id| registry.get(id)) else {
+        return;
     };
+    commands.spawn((
+        SpellIndicator,
+        PbrBundle {
+            mesh: asset_handles.meshes[spell.indicator.mesh as usize].clone(),
+            material: asset_handles.mats[spell.indicator.material as usize].clone(),
+            transform: Transform::from_translation(palm_mid_point.0)
+                .with_scale(INDICATOR_SCALE * Vec3::ONE),
+            ..Default::default()
+        },
+    ));
 }
 
 pub fn spawn_trajectory_indicator(
     mut commands: Commands,
+    registry: Res<SpellRegistry>,
     selected_spell: Res<SelectedSpell>,
     palm_mid_point: Res<SpellSpawnLocation>,
 ) {
-    match selected_spell.0.unwrap() {
-        Spell::Fireball => {
-            commands.spawn((
-                TrajectoryIndicator {
-                    despawn_on_fire: true,
-                },
-                StraightLaserTrajInd,
-                SpatialBundle::default(),
-            ));
-        }
-        Spell::Lightning => {
-            commands.spawn((
-                TrajectoryIndicator {
-                    despawn_on_fire: true,
-                },
-                StraightLaserTrajInd,
-                SpatialBundle {
-                    transform: Transform::from_translation(palm_mid_point.0),
-                    ..default()
-                },
-            ));
-        }
-        Spell::Parry => {}
-        Spell::Bomb => {}
-        Spell::Wall => {}
-        Spell::MagicMissile => {
+    let Some(spell) = selected_spell.0.and_then(|id| registry.get(id)) else {
+        return;
+    };
+    match spell.trajectory {
+        TrajectoryKind::None => {}
+        TrajectoryKind::StraightLaser => {
             commands.spawn((
                 TrajectoryIndicator {
                     despawn_on_fire: true,
@@ -796,33 +798,37 @@ pub fn spawn_trajectory_indicator(
     }
 }
 
+// Shows the ray the spell will be cast along, worked out the same way every peer will.
 fn handle_straight_laser_traj_ind(
     mut traj_ind: Query<&mut Transform, With<StraightLaserTrajInd>>,
     spatial_query: SpatialQuery,
     mut gizmos: Gizmos,
-    left_eye: Query<&Transform, (With<OpenXRLeftEye>, Without<StraightLaserTrajInd>)>,
-    right_eye: Query<&Transform, (With<OpenXRRightEye>, Without<StraightLaserTrajInd>)>,
-    palm_mid_point: Res<SpellSpawnLocation>,
+    last_input: Res<LastLocalInput>,
+    anchor: Res<SharedAnchor>,
+    bosses: Query<&Transform, (With<Boss>, Without<StraightLaserTrajInd>)>,
+    registry: Res<SpellRegistry>,
+    selected_spell: Res<SelectedSpell>,
 ) {
-    let left_eye = left_eye.get_single().unwrap();
-    let right_eye = right_eye.get_single().unwrap();
-
-    let head_transform =
-        Transform::from_translation(left_eye.translation.lerp(right_eye.translation, 0.5))
-            .with_rotation(left_eye.rotation);
-
     let mut t = match traj_ind.get_single_mut() {
         Ok(t) => t,
         _ => return,
     };
-
-    t.translation = palm_mid_point.0;
+    let assist_degrees = selected_spell
+        .0
+        .and_then(|id| registry.get(id))
+        .map_or(0.0, |s| s.aim_assist_degrees);
+    *t = aim_spell(
+        &last_input.0,
+        &anchor.0,
+        bosses.iter().map(|b| b.translation),
+        assist_degrees,
+    );
 
     let max_travel = 50.0;
 
     let ray_travel = match spatial_query.cast_ray(
         t.translation,
-        head_transform.forward(),
+        t.forward(),
         max_travel,
         true,
         SpatialQueryFilter::from_mask([PhysLayer::Terrain, PhysLayer::Boss]),
@@ -832,13 +838,16 @@ fn handle_straight_laser_traj_ind(
     };
     gizmos.line(
         t.translation,
-        t.translation + (head_transform.forward() * ray_travel),
+        t.translation + (t.forward() * ray_travel),
         Color::RED,
     ); // TODO don't use gizmos for line drawing
 }
 
 fn track_spell_indicator(
     palm_mid_point: Res<SpellSpawnLocation>,
+    registry: Res<SpellRegistry>,
+    selected_spell: Res<SelectedSpell>,
+    charge: Res<SpellCharge>,
     mut spell_indicator: Query<&mut Transform, With<SpellIndicator>>,
 ) {
     let mut t = match spell_indicator.get_single_mut() {
@@ -847,6 +856,13 @@ fn track_spell_indicator(
     };
 
     t.translation = palm_mid_point.0;
+    // Grows as it charges, to the size it'll be when it's fully charged
+    let grown = selected_spell
+        .0
+        .and_then(|id| registry.get(id))
+        .and_then(|s| s.charge)
+        .map_or(1.0, |c| c.scale(charge.0));
+    t.scale = INDICATOR_SCALE * grown * Vec3::ONE;
 }
 
 // Generic system for despawning entities on a timer.